@@ -0,0 +1,324 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+
+use crate::datetime::{parse_since, parse_until};
+
+/// MPRIS music scrobbler daemon.
+#[derive(Parser, Debug)]
+#[command(name = "rescrobbled", version = concat!("v", env!("CARGO_PKG_VERSION")))]
+pub struct Cli {
+    /// Use this config file instead of ~/.config/rescrobbled/config.toml
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Show more log messages (can be repeated)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show fewer log messages (can be repeated)
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The number of levels by which to raise or lower the log level.
+    pub fn verbosity(&self) -> i8 {
+        self.verbose as i8 - self.quiet as i8
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the scrobbler (default)
+    Run,
+
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: Option<ConfigCommand>,
+    },
+
+    /// Authenticate with a scrobbling service
+    Auth {
+        #[command(subcommand)]
+        service: AuthService,
+    },
+
+    /// List plays from the listening history
+    History(HistoryArgs),
+
+    /// Show listening statistics from the listening history
+    Stats(StatsArgs),
+
+    /// Import scrobbles from a .scrobbler.log file or a ListenBrainz export, and submit them
+    Import {
+        file: PathBuf,
+
+        /// The format of the file (detected automatically by default)
+        #[arg(short, long, value_enum)]
+        format: Option<ImportFormat>,
+
+        /// Only show which scrobbles would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Export scrobbles from the listening history
+    Export {
+        /// The format to export to
+        #[arg(short, long, value_enum)]
+        format: ExportFormat,
+
+        /// Only export scrobbles since this time (e.g. 2026-10-01, "2026-10-01 18:00", yesterday, 3d)
+        #[arg(long, value_name = "TIME", value_parser = parse_since)]
+        since: Option<DateTime<Local>>,
+
+        /// Only export scrobbles before this time; a date includes the whole day
+        #[arg(long, value_name = "TIME", value_parser = parse_until)]
+        until: Option<DateTime<Local>>,
+
+        /// Write to this file instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Run the normalization and filter script on a track and show the result
+    TestFilter {
+        artist: String,
+        title: String,
+        album: Option<String>,
+
+        /// Genre to pass to the filter script (can be repeated)
+        #[arg(short, long)]
+        genre: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Show the loaded config, including environment variable overrides (default)
+    Show,
+
+    /// Check the config file for errors
+    Check {
+        /// Treat unknown options as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Print an example config file
+    Template,
+
+    /// Print the path of the config file
+    Path,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    /// Only show plays since this time (e.g. 2026-10-01, "2026-10-01 18:00", yesterday, 3d)
+    #[arg(long, value_name = "TIME", value_parser = parse_since)]
+    pub since: Option<DateTime<Local>>,
+
+    /// Only show plays before this time; a date includes the whole day
+    #[arg(long, value_name = "TIME", value_parser = parse_until)]
+    pub until: Option<DateTime<Local>>,
+
+    /// Only show plays by artists containing this text
+    #[arg(long)]
+    pub artist: Option<String>,
+
+    /// Only show plays from players whose bus name contains this text
+    #[arg(long)]
+    pub player: Option<String>,
+
+    /// Only show plays submitted to services whose name contains this text
+    #[arg(long)]
+    pub service: Option<String>,
+
+    /// Only show plays that failed to submit, or were rejected by a service
+    #[arg(long)]
+    pub failed: bool,
+
+    /// Show at most this many of the most recent plays (0 shows all)
+    #[arg(short = 'n', long, default_value_t = 50)]
+    pub limit: usize,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: HistoryFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum HistoryFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Start of the period (e.g. 2026-10-01, "2026-10-01 18:00", yesterday, 4w)
+    #[arg(long, value_name = "TIME", value_parser = parse_since, default_value = "7d")]
+    pub since: DateTime<Local>,
+
+    /// End of the period (default: now); a date includes the whole day
+    #[arg(long, value_name = "TIME", value_parser = parse_until)]
+    pub until: Option<DateTime<Local>>,
+
+    /// Include all plays in the history, instead of the period starting at --since
+    #[arg(long, conflicts_with = "since")]
+    pub all: bool,
+
+    /// Show listening time per day or per week
+    #[arg(long, value_enum, default_value_t)]
+    pub by: StatsPeriod,
+
+    /// The number of top artists, albums and tracks to show
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub limit: usize,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: StatsFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum StatsPeriod {
+    #[default]
+    Day,
+    Week,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum StatsFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ImportFormat {
+    /// Audioscrobbler log, as written by Rockbox and other portable players
+    ScrobblerLog,
+    /// ListenBrainz export (a JSON array of listens, or one listen per line)
+    ListenbrainzJson,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// CSV with the same columns as Last.fm scrobble exports
+    Csv,
+    /// ListenBrainz export (a JSON array of listens)
+    ListenbrainzJson,
+    /// Audioscrobbler log, with timestamps in UTC
+    ScrobblerLog,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuthService {
+    /// Log in to Last.fm by authorizing rescrobbled in a browser
+    Lastfm {
+        /// The `[[lastfm]]` account to log in to, if multiple accounts are configured
+        username: Option<String>,
+    },
+
+    /// Store a secret for the `*-secret-service` config options, read from standard input
+    SecretService {
+        /// The name of the secret, as used in the config file
+        name: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["rescrobbled", "-vv", "--config", "/tmp/config.toml"]);
+        assert_eq!(cli.verbosity(), 2);
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/config.toml")));
+        assert!(cli.command.is_none());
+
+        let cli = Cli::parse_from(["rescrobbled", "config", "check", "-q", "--strict"]);
+        assert_eq!(cli.verbosity(), -1);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Config {
+                command: Some(ConfigCommand::Check { strict: true })
+            })
+        ));
+
+        let cli = Cli::parse_from([
+            "rescrobbled",
+            "history",
+            "--since",
+            "2026-10-01",
+            "--failed",
+            "-f",
+            "csv",
+        ]);
+        let Some(Command::History(args)) = cli.command else {
+            panic!("expected history command");
+        };
+        assert_eq!(args.since, Some(parse_since("2026-10-01").unwrap()));
+        assert!(args.failed);
+        assert_eq!(args.limit, 50);
+        assert_eq!(args.format, HistoryFormat::Csv);
+
+        assert!(Cli::try_parse_from(["rescrobbled", "history", "--until", "soon"]).is_err());
+
+        let cli = Cli::parse_from(["rescrobbled", "stats", "--by", "week", "-f", "json"]);
+        let Some(Command::Stats(args)) = cli.command else {
+            panic!("expected stats command");
+        };
+        assert!(args.since < Local::now());
+        assert_eq!(args.by, StatsPeriod::Week);
+        assert_eq!(args.format, StatsFormat::Json);
+
+        let cli = Cli::parse_from([
+            "rescrobbled",
+            "export",
+            "-f",
+            "scrobbler-log",
+            "-o",
+            "out.log",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Export {
+                format: ExportFormat::ScrobblerLog,
+                since: None,
+                output: Some(_),
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["rescrobbled", "export"]).is_err());
+    }
+}
@@ -14,22 +14,33 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 pub mod secrets;
+pub mod validate;
 
 use std::env::{self, VarError};
 use std::fs::{self, Permissions};
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result, anyhow, bail};
 
-use regex::RegexSet;
+use log::{LevelFilter, warn};
+
+use regex::{Regex, RegexSet};
 
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::config::secrets::{LastFmKey, LastFmSecret, ListenBrainzGlobalToken, ListenBrainzToken};
+use crate::config::secrets::{
+    LastFmAccountKey, LastFmAccountSecret, LastFmKey, LastFmSecret, ListenBrainzGlobalToken,
+    ListenBrainzToken,
+};
+use crate::config::validate::Severity;
+use crate::logging::LogFormat;
+use crate::normalize::JunkPatterns;
+use crate::secret_service::SessionStore;
+use crate::stream;
 
 const CONFIG_DIR: &str = "rescrobbled";
 const CONFIG_FILE: &str = "config.toml";
@@ -70,13 +81,128 @@ fn serialize_regex_set<S: Serializer>(value: &Option<RegexSet>, se: S) -> Result
     }
 }
 
+fn deserialize_regex<'de, D: Deserializer<'de>>(de: D) -> Result<Regex, D::Error> {
+    Regex::new(&String::deserialize(de)?).map_err(serde::de::Error::custom)
+}
+
+fn serialize_regex<S: Serializer>(value: &Regex, se: S) -> Result<S::Ok, S::Error> {
+    se.serialize_str(value.as_str())
+}
+
+/// What to do with tracks whose length is not reported by the player.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownLength {
+    /// Scrobble after a fixed play time.
+    #[default]
+    PlayTime,
+    /// Never scrobble.
+    Never,
+    /// Scrobble when the track ends, if it played longer than the minimum track length.
+    TrackChange,
+}
+
+/// When a track has played long enough to be scrobbled.
+/// Can be set at the top level, and overridden in a `[[player]]` definition.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Thresholds {
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration_seconds",
+        serialize_with = "serialize_duration_seconds"
+    )]
+    pub min_play_time: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration_seconds",
+        serialize_with = "serialize_duration_seconds"
+    )]
+    pub min_track_length: Option<Duration>,
+    pub scrobble_percent: Option<u8>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration_seconds",
+        serialize_with = "serialize_duration_seconds"
+    )]
+    pub scrobble_max_seconds: Option<Duration>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration_seconds",
+        serialize_with = "serialize_duration_seconds"
+    )]
+    pub unknown_length_play_time: Option<Duration>,
+    pub unknown_length: Option<UnknownLength>,
+}
+
+impl Thresholds {
+    /// Use the thresholds from `fallback` for the ones that are not set.
+    /// `min-play-time` is not used from `fallback` if `scrobble-percent` or
+    /// `scrobble-max-seconds` is set, because it would override them.
+    pub fn or(&self, fallback: &Thresholds) -> Thresholds {
+        let uses_track_length =
+            self.scrobble_percent.is_some() || self.scrobble_max_seconds.is_some();
+
+        Thresholds {
+            min_play_time: match uses_track_length {
+                true => self.min_play_time,
+                false => self.min_play_time.or(fallback.min_play_time),
+            },
+            min_track_length: self.min_track_length.or(fallback.min_track_length),
+            scrobble_percent: self.scrobble_percent.or(fallback.scrobble_percent),
+            scrobble_max_seconds: self.scrobble_max_seconds.or(fallback.scrobble_max_seconds),
+            unknown_length_play_time: self
+                .unknown_length_play_time
+                .or(fallback.unknown_length_play_time),
+            unknown_length: self.unknown_length.or(fallback.unknown_length),
+        }
+    }
+}
+
+/// Settings for the players matching a pattern, in a `[[player]]` definition.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PlayerConfig {
+    #[serde(
+        deserialize_with = "deserialize_regex",
+        serialize_with = "serialize_regex"
+    )]
+    pub name: Regex,
+    /// Whether the player plays internet streams, instead of detecting this from the URL.
+    pub stream: Option<bool>,
+    /// Whether to normalize the player's tracks, regardless of the `[normalize]` section.
+    pub normalize: Option<bool>,
+    #[serde(flatten)]
+    pub thresholds: Thresholds,
+}
+
+/// Which steps of the built-in normalization to use, in the `[normalize]` section.
+/// All steps are enabled by default if the section is present.
 #[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct NormalizeConfig {
+    pub split_artist: Option<bool>,
+    pub strip_junk: Option<bool>,
+    pub junk_patterns: Option<JunkPatterns>,
+    pub strip_channel_suffixes: Option<bool>,
+    pub channel_suffixes: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, PartialEq)]
 pub struct ListenBrainzConfig {
     pub url: Option<String>,
     #[serde(flatten)]
     pub token: ListenBrainzToken,
 }
 
+#[derive(Deserialize, Serialize, Default, Debug, PartialEq)]
+pub struct LastFmConfig {
+    pub username: Option<String>,
+    #[serde(flatten)]
+    pub key: LastFmAccountKey,
+    #[serde(flatten)]
+    pub secret: LastFmAccountSecret,
+}
+
 #[derive(Deserialize, Serialize, Default, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
@@ -84,14 +210,12 @@ pub struct Config {
     pub lastfm_key: Option<LastFmKey>,
     #[serde(flatten)]
     pub lastfm_secret: Option<LastFmSecret>,
+    pub lastfm_username: Option<String>,
+    pub session_store: Option<SessionStore>,
     #[serde(flatten)]
     pub listenbrainz_token: Option<ListenBrainzGlobalToken>,
-    #[serde(
-        default,
-        deserialize_with = "deserialize_duration_seconds",
-        serialize_with = "serialize_duration_seconds"
-    )]
-    pub min_play_time: Option<Duration>,
+    #[serde(flatten)]
+    pub thresholds: Thresholds,
     #[serde(
         default,
         deserialize_with = "deserialize_regex_set",
@@ -105,7 +229,16 @@ pub struct Config {
     )]
     pub player_ignorelist: Option<RegexSet>,
     pub filter_script: Option<PathBuf>,
+    pub stream_title_separators: Option<Vec<String>>,
     pub use_track_start_timestamp: Option<bool>,
+    pub watch_config: Option<bool>,
+    pub scrobble_on_exit: Option<bool>,
+    pub log_level: Option<LevelFilter>,
+    pub log_format: Option<LogFormat>,
+    pub strict_config: Option<bool>,
+    pub normalize: Option<NormalizeConfig>,
+    pub player: Option<Vec<PlayerConfig>>,
+    pub lastfm: Option<Vec<LastFmConfig>>,
     pub listenbrainz: Option<Vec<ListenBrainzConfig>>,
 }
 
@@ -114,12 +247,41 @@ impl Config {
         let template = Config {
             lastfm_key: Some(LastFmKey::default()),
             lastfm_secret: Some(LastFmSecret::default()),
+            lastfm_username: None,
+            session_store: Some(SessionStore::File),
             listenbrainz_token: None,
-            min_play_time: Some(Duration::from_secs(0)),
+            thresholds: Thresholds {
+                min_play_time: None,
+                min_track_length: Some(Duration::from_secs(30)),
+                scrobble_percent: Some(50),
+                scrobble_max_seconds: Some(Duration::from_secs(4 * 60)),
+                unknown_length_play_time: None,
+                unknown_length: None,
+            },
             player_whitelist: Some(RegexSet::default()),
             player_ignorelist: Some(RegexSet::default()),
             filter_script: Some(PathBuf::new()),
+            stream_title_separators: Some(
+                stream::DEFAULT_SEPARATORS
+                    .iter()
+                    .map(|separator| separator.to_string())
+                    .collect(),
+            ),
             use_track_start_timestamp: Some(false),
+            watch_config: Some(false),
+            scrobble_on_exit: Some(true),
+            log_level: Some(LevelFilter::Info),
+            log_format: Some(LogFormat::Text),
+            strict_config: Some(false),
+            normalize: Some(NormalizeConfig {
+                split_artist: Some(true),
+                strip_junk: Some(true),
+                junk_patterns: None,
+                strip_channel_suffixes: Some(true),
+                channel_suffixes: None,
+            }),
+            player: None,
+            lastfm: None,
             listenbrainz: Some(vec![ListenBrainzConfig {
                 url: Some(String::new()),
                 token: ListenBrainzToken::default(),
@@ -132,18 +294,39 @@ impl Config {
             .collect()
     }
 
+    /// The separators between the artist and title in combined titles.
+    pub fn title_separators(&self) -> Vec<&str> {
+        match self.stream_title_separators {
+            Some(ref separators) => separators.iter().map(String::as_str).collect(),
+            None => stream::DEFAULT_SEPARATORS.to_vec(),
+        }
+    }
+
     fn normalize(&mut self) {
+        // Turn `lastfm-key` and `lastfm-secret` into a `[[lastfm]]` definition.
+        // If `[[lastfm]]` is also defined, they are ignored (validation warns about this)
+        let key = self.lastfm_key.take();
+        let secret = self.lastfm_secret.take();
+        let username = self.lastfm_username.take();
+
+        if let (Some(key), Some(secret)) = (key, secret)
+            && self.lastfm.is_none()
+        {
+            self.lastfm = Some(vec![LastFmConfig {
+                username,
+                key: key.into(),
+                secret: secret.into(),
+            }]);
+        }
+
         // Turn `listenbrainz-token` into a `[[listenbrainz]]` definition
         if self.listenbrainz_token.is_some() {
+            // If both are defined, `listenbrainz-token` is ignored (validation warns about this)
             if self.listenbrainz.is_none() {
                 self.listenbrainz = Some(vec![ListenBrainzConfig {
                     url: None,
                     token: self.listenbrainz_token.take().unwrap().into(),
                 }])
-            } else {
-                eprintln!(
-                    "Warning: both listenbrainz-token and [[listenbrainz]] config options are defined (listenbrainz-token will be ignored)"
-                );
             }
 
             self.listenbrainz_token.take();
@@ -196,7 +379,7 @@ fn override_from_environment(config: &mut Config) -> Result<()> {
         get_envvar("LISTENBRAINZ_TOKEN")?.map(ListenBrainzGlobalToken::Inline),
     );
     replace_if_some(
-        &mut config.min_play_time,
+        &mut config.thresholds.min_play_time,
         get_envvar::<u64>("MIN_PLAY_TIME").map(|t| t.map(Duration::from_secs))?,
     );
     replace_if_some(&mut config.filter_script, get_envvar("FILTER_SCRIPT")?);
@@ -204,18 +387,24 @@ fn override_from_environment(config: &mut Config) -> Result<()> {
         &mut config.use_track_start_timestamp,
         get_envvar("USE_TRACK_START_TIMESTAMP")?,
     );
+    replace_if_some(&mut config.log_level, get_envvar("LOG_LEVEL")?);
 
     Ok(())
 }
 
-pub fn load_config() -> Result<Config> {
+pub fn config_path() -> Result<PathBuf> {
     let mut path = config_dir()?;
 
     path.push(CONFIG_FILE);
 
+    Ok(path)
+}
+
+/// Load the config file, creating a template if it does not exist yet.
+pub fn load_config(path: &Path) -> Result<Config> {
     if !path.exists() {
-        fs::write(&path, Config::template()).context("Failed to create config template")?;
-        fs::set_permissions(&path, Permissions::from_mode(0o600))
+        fs::write(path, Config::template()).context("Failed to create config template")?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))
             .context("Failed to set permissions for config file")?;
 
         bail!(
@@ -224,15 +413,60 @@ pub fn load_config() -> Result<Config> {
         );
     }
 
-    let buffer = fs::read_to_string(&path).context("Failed to open config file")?;
+    read_config(path)
+}
+
+/// Read and parse an existing config file, without creating a template if it
+/// does not exist. This is used when reloading the config at runtime.
+pub fn read_config(path: &Path) -> Result<Config> {
+    let buffer = fs::read_to_string(path).context("Failed to open config file")?;
+
+    let (config, diagnostics) = validate::validate(&buffer, false);
+
+    let mut errors = Vec::new();
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Warning => warn!("{}", diagnostic.summary(path)),
+            Severity::Error => errors.push(diagnostic.render(path)),
+        }
+    }
+
+    match config {
+        Some(config) if errors.is_empty() => Ok(config),
+        _ => bail!("Invalid config file:\n\n{}", errors.join("\n\n")),
+    }
+}
 
-    let mut config: Config = toml::from_str(&buffer).context("Failed to parse config file")?;
+/// Keeps track of the modification time of the config file,
+/// to be able to reload the config when it changes.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
 
-    override_from_environment(&mut config)?;
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = Self::modified(&path);
+        Self { path, modified }
+    }
 
-    config.normalize();
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-    Ok(config)
+    /// Returns whether the config file was modified since the last call.
+    pub fn changed(&mut self) -> bool {
+        let modified = Self::modified(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            return modified.is_some();
+        }
+        false
+    }
 }
 
 #[cfg(test)]
@@ -243,7 +477,8 @@ mod tests {
 
     use super::*;
 
-    static ENV_LOCK: Mutex<()> = Mutex::new(());
+    /// Serializes tests that read or modify environment variables.
+    pub(super) static ENV_LOCK: Mutex<()> = Mutex::new(());
 
     #[test]
     fn test_normalize_empty_config() {
@@ -282,6 +517,52 @@ mod tests {
         assert!(config.listenbrainz.is_some());
     }
 
+    #[test]
+    fn test_normalize_lastfm() {
+        let mut config = Config {
+            lastfm_key: Some(LastFmKey::Inline("KEY".to_string())),
+            lastfm_secret: Some(LastFmSecret::File("secret.txt".to_string())),
+            lastfm_username: Some("user".to_string()),
+            ..Default::default()
+        };
+        config.normalize();
+
+        assert!(config.lastfm_key.is_none());
+        assert!(config.lastfm_secret.is_none());
+        assert!(config.lastfm_username.is_none());
+        assert_eq!(
+            config.lastfm,
+            Some(vec![LastFmConfig {
+                username: Some("user".to_string()),
+                key: LastFmAccountKey::Inline("KEY".to_string()),
+                secret: LastFmAccountSecret::File("secret.txt".to_string()),
+            }])
+        );
+
+        // `[[lastfm]]` takes precedence
+        let accounts = vec![
+            LastFmConfig {
+                username: Some("first".to_string()),
+                ..Default::default()
+            },
+            LastFmConfig {
+                username: Some("second".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let mut config: Config = toml::from_str(
+            "lastfm-key = \"KEY\"\nlastfm-secret = \"SECRET\"\n\
+            [[lastfm]]\nusername = \"first\"\nkey = \"\"\nsecret = \"\"\n\
+            [[lastfm]]\nusername = \"second\"\nkey = \"\"\nsecret = \"\"\n",
+        )
+        .unwrap();
+        config.normalize();
+
+        assert!(config.lastfm_key.is_none());
+        assert_eq!(config.lastfm, Some(accounts));
+    }
+
     #[test]
     fn test_override_from_environment() {
         let mut config = Config::default();
@@ -297,9 +578,27 @@ mod tests {
             std::env::set_var("MIN_PLAY_TIME", "30");
             std::env::set_var("FILTER_SCRIPT", "/tmp/filter.sh");
             std::env::set_var("USE_TRACK_START_TIMESTAMP", "true");
+            std::env::set_var("LOG_LEVEL", "debug");
+        }
+
+        let result = override_from_environment(&mut config);
+
+        // Safety: see above
+        unsafe {
+            for name in [
+                "LASTFM_KEY",
+                "LASTFM_SECRET",
+                "LISTENBRAINZ_TOKEN",
+                "MIN_PLAY_TIME",
+                "FILTER_SCRIPT",
+                "USE_TRACK_START_TIMESTAMP",
+                "LOG_LEVEL",
+            ] {
+                std::env::remove_var(name);
+            }
         }
 
-        override_from_environment(&mut config).unwrap();
+        result.unwrap();
 
         assert_eq!(
             config.lastfm_key,
@@ -315,12 +614,38 @@ mod tests {
                 "listenbrainz_token_xyz".to_string()
             ))
         );
-        assert_eq!(config.min_play_time, Some(Duration::from_secs(30)));
+        assert_eq!(
+            config.thresholds.min_play_time,
+            Some(Duration::from_secs(30))
+        );
         assert_eq!(
             config.filter_script.as_deref(),
             Some(Path::new("/tmp/filter.sh"))
         );
         assert_eq!(config.use_track_start_timestamp, Some(true));
+        assert_eq!(config.log_level, Some(LevelFilter::Debug));
+    }
+
+    #[test]
+    fn test_config_watcher() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(CONFIG_FILE);
+
+        fs::write(&path, "").unwrap();
+
+        let mut watcher = ConfigWatcher::new(path.clone());
+        assert!(!watcher.changed());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        // A removed config file should not trigger a reload
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
     }
 
     #[test]
@@ -330,4 +655,54 @@ mod tests {
             Cow::<str>::Owned("something secret".to_string())
         )
     }
+
+    #[test]
+    fn test_secrets_from_command() {
+        assert_eq!(
+            LastFmSecret::Command("echo '  something secret  '".to_string())
+                .get()
+                .unwrap(),
+            "something secret"
+        );
+
+        // The output is cached
+        let secret = ListenBrainzToken::Command("date +%s%N".to_string());
+        assert_eq!(secret.get().unwrap(), secret.get().unwrap());
+
+        let err = LastFmKey::Command("echo 'no such entry' >&2; exit 3".to_string())
+            .get()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`echo 'no such entry' >&2; exit 3` failed (exit status: 3): no such entry"
+        );
+
+        assert!(LastFmKey::Command("true".to_string()).get().is_err());
+    }
+
+    #[test]
+    fn test_secrets_from_secret_service() {
+        let config: Config = toml::from_str(
+            "lastfm-key-secret-service = \"lastfm-key\"\n\
+            lastfm-secret-secret-service = \"lastfm-secret\"\n\
+            [[listenbrainz]]\ntoken-secret-service = \"listenbrainz\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.lastfm_key,
+            Some(LastFmKey::SecretService("lastfm-key".to_string()))
+        );
+        assert_eq!(
+            config.lastfm_secret,
+            Some(LastFmSecret::SecretService("lastfm-secret".to_string()))
+        );
+        assert_eq!(
+            config.listenbrainz,
+            Some(vec![ListenBrainzConfig {
+                url: None,
+                token: ListenBrainzToken::SecretService("listenbrainz".to_string()),
+            }])
+        );
+    }
 }
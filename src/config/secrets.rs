@@ -1,14 +1,23 @@
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
 use std::{borrow::Cow, fs, path::PathBuf, str::FromStr};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 
+use crate::secret_service::SecretService;
+
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub enum ListenBrainzToken {
     #[serde(rename = "token")]
     Inline(String),
     #[serde(rename = "token-file")]
     File(String),
+    #[serde(rename = "token-command")]
+    Command(String),
+    #[serde(rename = "token-secret-service")]
+    SecretService(String),
 }
 
 impl Default for ListenBrainzToken {
@@ -24,6 +33,10 @@ pub enum ListenBrainzGlobalToken {
     Inline(String),
     #[serde(rename = "listenbrainz-token-file")]
     File(String),
+    #[serde(rename = "listenbrainz-token-command")]
+    Command(String),
+    #[serde(rename = "listenbrainz-token-secret-service")]
+    SecretService(String),
 }
 
 impl Default for ListenBrainzGlobalToken {
@@ -37,6 +50,8 @@ impl From<ListenBrainzGlobalToken> for ListenBrainzToken {
         match value {
             ListenBrainzGlobalToken::Inline(v) => ListenBrainzToken::Inline(v),
             ListenBrainzGlobalToken::File(v) => ListenBrainzToken::File(v),
+            ListenBrainzGlobalToken::Command(v) => ListenBrainzToken::Command(v),
+            ListenBrainzGlobalToken::SecretService(v) => ListenBrainzToken::SecretService(v),
         }
     }
 }
@@ -48,6 +63,10 @@ pub enum LastFmKey {
     Inline(String),
     #[serde(rename = "lastfm-key-file")]
     File(String),
+    #[serde(rename = "lastfm-key-command")]
+    Command(String),
+    #[serde(rename = "lastfm-key-secret-service")]
+    SecretService(String),
 }
 
 impl Default for LastFmKey {
@@ -63,6 +82,10 @@ pub enum LastFmSecret {
     Inline(String),
     #[serde(rename = "lastfm-secret-file")]
     File(String),
+    #[serde(rename = "lastfm-secret-command")]
+    Command(String),
+    #[serde(rename = "lastfm-secret-secret-service")]
+    SecretService(String),
 }
 
 impl Default for LastFmSecret {
@@ -71,9 +94,144 @@ impl Default for LastFmSecret {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum LastFmAccountKey {
+    #[serde(rename = "key")]
+    Inline(String),
+    #[serde(rename = "key-file")]
+    File(String),
+    #[serde(rename = "key-command")]
+    Command(String),
+    #[serde(rename = "key-secret-service")]
+    SecretService(String),
+}
+
+impl Default for LastFmAccountKey {
+    fn default() -> Self {
+        Self::Inline(String::default())
+    }
+}
+
+impl From<LastFmKey> for LastFmAccountKey {
+    fn from(value: LastFmKey) -> Self {
+        match value {
+            LastFmKey::Inline(v) => LastFmAccountKey::Inline(v),
+            LastFmKey::File(v) => LastFmAccountKey::File(v),
+            LastFmKey::Command(v) => LastFmAccountKey::Command(v),
+            LastFmKey::SecretService(v) => LastFmAccountKey::SecretService(v),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub enum LastFmAccountSecret {
+    #[serde(rename = "secret")]
+    Inline(String),
+    #[serde(rename = "secret-file")]
+    File(String),
+    #[serde(rename = "secret-command")]
+    Command(String),
+    #[serde(rename = "secret-secret-service")]
+    SecretService(String),
+}
+
+impl Default for LastFmAccountSecret {
+    fn default() -> Self {
+        Self::Inline(String::default())
+    }
+}
+
+impl From<LastFmSecret> for LastFmAccountSecret {
+    fn from(value: LastFmSecret) -> Self {
+        match value {
+            LastFmSecret::Inline(v) => LastFmAccountSecret::Inline(v),
+            LastFmSecret::File(v) => LastFmAccountSecret::File(v),
+            LastFmSecret::Command(v) => LastFmAccountSecret::Command(v),
+            LastFmSecret::SecretService(v) => LastFmAccountSecret::SecretService(v),
+        }
+    }
+}
+
+/// Where the value of a secret comes from.
+#[derive(Debug, PartialEq)]
+pub enum SecretSource<'a> {
+    Inline(&'a str),
+    File(&'a str),
+    Command(&'a str),
+    SecretService(&'a str),
+}
+
+/// Output of secret commands, so each command is only run once per process.
+static COMMAND_CACHE: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+/// Secrets from the Secret Service, so each secret is only looked up once per process.
+static SECRET_SERVICE_CACHE: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// Run a command using the shell and return its trimmed output, or the cached output
+/// if the command was run before.
+fn run_command(command: &str) -> Result<String> {
+    let mut cache = COMMAND_CACHE.lock().unwrap_or_else(|err| err.into_inner());
+
+    if let Some(secret) = cache.get(command) {
+        return Ok(secret.clone());
+    }
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run `{command}`"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+
+        if stderr.is_empty() {
+            bail!("`{command}` failed ({})", output.status);
+        }
+        bail!("`{command}` failed ({}): {stderr}", output.status);
+    }
+
+    let secret = String::from_utf8(output.stdout)
+        .with_context(|| format!("Output of `{command}` is not valid UTF-8"))?
+        .trim()
+        .to_owned();
+
+    if secret.is_empty() {
+        bail!("`{command}` did not output anything");
+    }
+
+    cache.insert(command.to_owned(), secret.clone());
+
+    Ok(secret)
+}
+
+/// Look up a secret stored in the Secret Service under `name`, or return the cached secret
+/// if it was looked up before.
+fn lookup_secret(name: &str) -> Result<String> {
+    let mut cache = SECRET_SERVICE_CACHE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    if let Some(secret) = cache.get(name) {
+        return Ok(secret.clone());
+    }
+
+    let secret = SecretService::connect()?.lookup(name)?;
+
+    cache.insert(name.to_owned(), secret.clone());
+
+    Ok(secret)
+}
+
 pub trait Secret {
-    /// Returns the token if it's `Inline`, or fetches it from the specified `File`.
+    /// Returns the token if it's `Inline`, fetches it from the specified `File`,
+    /// gets it from the output of the specified `Command`, or looks it up in the
+    /// Secret Service.
     fn get(&'_ self) -> Result<Cow<'_, str>>;
+
+    /// Returns where the secret comes from, without resolving it.
+    fn source(&self) -> SecretSource<'_>;
 }
 
 // Because using derive macros is too complicated, and converting them all to a `Secret` enum is
@@ -93,6 +251,17 @@ macro_rules! impl_secret {
 
                         Ok(Cow::Owned(secret.trim().to_owned()))
                     }
+                    $name::Command(command) => run_command(command).map(Cow::Owned),
+                    $name::SecretService(name) => lookup_secret(name).map(Cow::Owned),
+                }
+            }
+
+            fn source(&self) -> SecretSource<'_> {
+                match self {
+                    $name::Inline(secret) => SecretSource::Inline(secret),
+                    $name::File(path) => SecretSource::File(path),
+                    $name::Command(command) => SecretSource::Command(command),
+                    $name::SecretService(name) => SecretSource::SecretService(name),
                 }
             }
         }
@@ -103,3 +272,5 @@ impl_secret!(ListenBrainzToken);
 impl_secret!(ListenBrainzGlobalToken);
 impl_secret!(LastFmKey);
 impl_secret!(LastFmSecret);
+impl_secret!(LastFmAccountKey);
+impl_secret!(LastFmAccountSecret);
@@ -0,0 +1,1204 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Validation of config files, with diagnostics pointing at the offending line and column.
+
+use std::fmt::Write;
+use std::fs;
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use toml::de::DeTable;
+
+use crate::config::secrets::{Secret, SecretSource};
+use crate::config::{Config, Thresholds, override_from_environment};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Location {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number, in characters.
+    pub column: usize,
+    /// The contents of the line.
+    text: String,
+    /// Length of the span on this line, in characters.
+    length: usize,
+}
+
+/// A problem found in a config file.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Format the diagnostic for display, similar to compiler errors.
+    pub fn render(&self, path: &Path) -> String {
+        let mut output = match self.severity {
+            Severity::Error => format!("error: {}", self.message),
+            Severity::Warning => format!("warning: {}", self.message),
+        };
+
+        match self.location {
+            Some(ref location) => {
+                let number = location.line.to_string();
+                let margin = " ".repeat(number.len());
+
+                write!(
+                    output,
+                    "\n{margin}--> {}:{}:{}\n\
+                    {margin} |\n\
+                    {number} | {}\n\
+                    {margin} | {}{}",
+                    path.display(),
+                    location.line,
+                    location.column,
+                    location.text,
+                    " ".repeat(location.column - 1),
+                    "^".repeat(location.length.max(1)),
+                )
+                .unwrap();
+
+                if let Some(ref help) = self.help {
+                    write!(output, "\n{margin} = help: {help}").unwrap();
+                }
+            }
+            None => {
+                write!(output, "\n --> {}", path.display()).unwrap();
+
+                if let Some(ref help) = self.help {
+                    write!(output, "\n  = help: {help}").unwrap();
+                }
+            }
+        }
+
+        output
+    }
+
+    /// Format the diagnostic as a single line, for use in log messages.
+    pub fn summary(&self, path: &Path) -> String {
+        let summary = match self.location {
+            Some(ref location) => format!(
+                "{} ({}:{}:{})",
+                self.message,
+                path.display(),
+                location.line,
+                location.column
+            ),
+            None => format!("{} ({})", self.message, path.display()),
+        };
+
+        match self.help {
+            Some(ref help) => format!("{summary}, {help}"),
+            None => summary,
+        }
+    }
+}
+
+/// The option names of a secret, for each way of specifying it.
+struct SecretOption {
+    inline: &'static [&'static str],
+    file: &'static str,
+    command: &'static str,
+    secret_service: &'static str,
+    env: Option<&'static str>,
+}
+
+const LASTFM_KEY: SecretOption = SecretOption {
+    inline: &["lastfm-key", "api-key"],
+    file: "lastfm-key-file",
+    command: "lastfm-key-command",
+    secret_service: "lastfm-key-secret-service",
+    env: Some("LASTFM_KEY"),
+};
+
+const LASTFM_SECRET: SecretOption = SecretOption {
+    inline: &["lastfm-secret", "api-secret"],
+    file: "lastfm-secret-file",
+    command: "lastfm-secret-command",
+    secret_service: "lastfm-secret-secret-service",
+    env: Some("LASTFM_SECRET"),
+};
+
+const LASTFM_ACCOUNT_KEY: SecretOption = SecretOption {
+    inline: &["key"],
+    file: "key-file",
+    command: "key-command",
+    secret_service: "key-secret-service",
+    env: None,
+};
+
+const LASTFM_ACCOUNT_SECRET: SecretOption = SecretOption {
+    inline: &["secret"],
+    file: "secret-file",
+    command: "secret-command",
+    secret_service: "secret-secret-service",
+    env: None,
+};
+
+const LISTENBRAINZ_GLOBAL_TOKEN: SecretOption = SecretOption {
+    inline: &["listenbrainz-token", "lb-token"],
+    file: "listenbrainz-token-file",
+    command: "listenbrainz-token-command",
+    secret_service: "listenbrainz-token-secret-service",
+    env: Some("LISTENBRAINZ_TOKEN"),
+};
+
+const LISTENBRAINZ_TOKEN: SecretOption = SecretOption {
+    inline: &["token"],
+    file: "token-file",
+    command: "token-command",
+    secret_service: "token-secret-service",
+    env: None,
+};
+
+/// All keys that can be used at the top level of the config file.
+const CONFIG_KEYS: &[&str] = &[
+    "lastfm-key",
+    "api-key",
+    "lastfm-key-file",
+    "lastfm-key-command",
+    "lastfm-key-secret-service",
+    "lastfm-secret",
+    "api-secret",
+    "lastfm-secret-file",
+    "lastfm-secret-command",
+    "lastfm-secret-secret-service",
+    "lastfm-username",
+    "session-store",
+    "listenbrainz-token",
+    "lb-token",
+    "listenbrainz-token-file",
+    "listenbrainz-token-command",
+    "listenbrainz-token-secret-service",
+    "min-play-time",
+    "min-track-length",
+    "scrobble-percent",
+    "scrobble-max-seconds",
+    "unknown-length-play-time",
+    "unknown-length",
+    "player-whitelist",
+    "player-ignorelist",
+    "filter-script",
+    "stream-title-separators",
+    "use-track-start-timestamp",
+    "watch-config",
+    "scrobble-on-exit",
+    "log-level",
+    "log-format",
+    "strict-config",
+    "normalize",
+    "player",
+    "lastfm",
+    "listenbrainz",
+];
+
+/// All keys that can be used in the `[normalize]` section.
+const NORMALIZE_KEYS: &[&str] = &[
+    "split-artist",
+    "strip-junk",
+    "junk-patterns",
+    "strip-channel-suffixes",
+    "channel-suffixes",
+];
+
+/// All keys that can be used in a `[[player]]` definition.
+const PLAYER_KEYS: &[&str] = &[
+    "name",
+    "stream",
+    "normalize",
+    "min-play-time",
+    "min-track-length",
+    "scrobble-percent",
+    "scrobble-max-seconds",
+    "unknown-length-play-time",
+    "unknown-length",
+];
+
+/// All keys that can be used in a `[[lastfm]]` definition.
+const LASTFM_KEYS: &[&str] = &[
+    "username",
+    "key",
+    "key-file",
+    "key-command",
+    "key-secret-service",
+    "secret",
+    "secret-file",
+    "secret-command",
+    "secret-secret-service",
+];
+
+/// All keys that can be used in a `[[listenbrainz]]` definition.
+const LISTENBRAINZ_KEYS: &[&str] = &[
+    "url",
+    "token",
+    "token-file",
+    "token-command",
+    "token-secret-service",
+];
+
+/// Compute the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Find the known key that is most similar to an unknown key, if any is similar enough.
+fn suggest<'k>(key: &str, known: &[&'k str]) -> Option<&'k str> {
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+struct Validator<'a> {
+    source: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn location(&self, span: Range<usize>) -> Location {
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(self.source.len());
+
+        let text = self.source[line_start..line_end].trim_end_matches('\r');
+        let end = span.end.clamp(start, line_start + text.len());
+
+        Location {
+            line: self.source[..start].matches('\n').count() + 1,
+            column: self.source[line_start..start].chars().count() + 1,
+            text: text.to_owned(),
+            length: self.source[start..end].chars().count(),
+        }
+    }
+
+    fn report(
+        &mut self,
+        severity: Severity,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        help: Option<String>,
+    ) {
+        let location = span.map(|span| self.location(span));
+
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message: message.into(),
+            location,
+            help,
+        });
+    }
+
+    fn error(
+        &mut self,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        help: Option<String>,
+    ) {
+        self.report(Severity::Error, span, message, help);
+    }
+
+    fn warning(
+        &mut self,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        help: Option<String>,
+    ) {
+        self.report(Severity::Warning, span, message, help);
+    }
+
+    fn check_secret(
+        &mut self,
+        table: Option<&DeTable>,
+        option: &SecretOption,
+        secret: &impl Secret,
+    ) {
+        let names = match secret.source() {
+            SecretSource::Inline(_) => option.inline,
+            SecretSource::File(_) => &[option.file],
+            SecretSource::Command(_) => &[option.command],
+            SecretSource::SecretService(_) => &[option.secret_service],
+        };
+
+        let (name, span) = match table.and_then(|table| find(table, names)) {
+            Some((name, _, value)) => (name.to_owned(), Some(value)),
+            // The value was set using an environment variable
+            None => (option.env.unwrap_or(names[0]).to_owned(), None),
+        };
+
+        match secret.source() {
+            SecretSource::Inline(value) if value.trim().is_empty() => self.error(
+                span,
+                format!("`{name}` is empty"),
+                Some("fill in the value, or remove the option".to_owned()),
+            ),
+            SecretSource::File(path) => {
+                if let Err(err) = secret.get() {
+                    self.error(
+                        span,
+                        format!("cannot read `{name}` file {path}: {err:#}"),
+                        Some("make sure the file exists and is readable".to_owned()),
+                    );
+                }
+            }
+            // Commands are only run and secrets are only looked up when connecting
+            // to the service, so that a failure only disables that service
+            SecretSource::Command(command) if command.trim().is_empty() => self.error(
+                span,
+                format!("`{name}` is empty"),
+                Some("fill in the command, or remove the option".to_owned()),
+            ),
+            SecretSource::SecretService(secret_name) if secret_name.trim().is_empty() => self
+                .error(
+                    span,
+                    format!("`{name}` is empty"),
+                    Some("fill in the name of the secret, or remove the option".to_owned()),
+                ),
+            _ => {}
+        }
+    }
+
+    fn check_lastfm(&mut self, table: &DeTable, config: &Config) {
+        let key = find(
+            table,
+            &[
+                LASTFM_KEY.inline,
+                &[
+                    LASTFM_KEY.file,
+                    LASTFM_KEY.command,
+                    LASTFM_KEY.secret_service,
+                ],
+            ]
+            .concat(),
+        )
+        .map(|(_, key, _)| key);
+        let secret = find(
+            table,
+            &[
+                LASTFM_SECRET.inline,
+                &[
+                    LASTFM_SECRET.file,
+                    LASTFM_SECRET.command,
+                    LASTFM_SECRET.secret_service,
+                ],
+            ]
+            .concat(),
+        )
+        .map(|(_, key, _)| key);
+
+        match (&config.lastfm_key, &config.lastfm_secret) {
+            (Some(_), None) => self.error(
+                key.clone(),
+                "`lastfm-key` is set, but `lastfm-secret` is missing",
+                Some(
+                    "add `lastfm-secret = \"<API secret>\"` (or `lastfm-secret-file`), \
+                    or remove `lastfm-key` to disable Last.fm"
+                        .to_owned(),
+                ),
+            ),
+            (None, Some(_)) => self.error(
+                secret.clone(),
+                "`lastfm-secret` is set, but `lastfm-key` is missing",
+                Some(
+                    "add `lastfm-key = \"<API key>\"` (or `lastfm-key-file`), \
+                    or remove `lastfm-secret` to disable Last.fm"
+                        .to_owned(),
+                ),
+            ),
+            _ => {}
+        }
+
+        if let Some(ref key) = config.lastfm_key {
+            self.check_secret(Some(table), &LASTFM_KEY, key);
+        }
+        if let Some(ref secret) = config.lastfm_secret {
+            self.check_secret(Some(table), &LASTFM_SECRET, secret);
+        }
+
+        if (config.lastfm_key.is_some() || config.lastfm_secret.is_some())
+            && config.lastfm.is_some()
+        {
+            self.warning(
+                key.or(secret),
+                "both `lastfm-key`/`lastfm-secret` and `[[lastfm]]` are defined \
+                (`lastfm-key` and `lastfm-secret` will be ignored)",
+                Some("move the key and secret into a `[[lastfm]]` definition".to_owned()),
+            );
+        }
+
+        let tables = array_tables(table, "lastfm");
+
+        for (i, lf) in config.lastfm.iter().flatten().enumerate() {
+            let table = tables.get(i).copied();
+
+            self.check_secret(table, &LASTFM_ACCOUNT_KEY, &lf.key);
+            self.check_secret(table, &LASTFM_ACCOUNT_SECRET, &lf.secret);
+        }
+    }
+
+    fn check_listenbrainz(&mut self, table: &DeTable, config: &Config) {
+        if let Some(ref token) = config.listenbrainz_token {
+            self.check_secret(Some(table), &LISTENBRAINZ_GLOBAL_TOKEN, token);
+
+            if config.listenbrainz.is_some() {
+                self.warning(
+                    find(table, LISTENBRAINZ_GLOBAL_TOKEN.inline).map(|(_, key, _)| key),
+                    "both `listenbrainz-token` and `[[listenbrainz]]` are defined \
+                    (`listenbrainz-token` will be ignored)",
+                    Some(
+                        "move the token into a `[[listenbrainz]]` definition without a `url`"
+                            .to_owned(),
+                    ),
+                );
+            }
+        }
+
+        let tables = array_tables(table, "listenbrainz");
+
+        for (i, lb) in config.listenbrainz.iter().flatten().enumerate() {
+            let table = tables.get(i).copied();
+
+            self.check_secret(table, &LISTENBRAINZ_TOKEN, &lb.token);
+
+            if let Some(ref url) = lb.url {
+                let span = table
+                    .and_then(|table| find(table, &["url"]))
+                    .map(|(_, _, value)| value);
+
+                let host = url
+                    .strip_prefix("https://")
+                    .or_else(|| url.strip_prefix("http://"))
+                    .map(|rest| rest.split('/').next().unwrap_or(""));
+
+                match host {
+                    Some(host) if !host.is_empty() && !host.contains(char::is_whitespace) => {
+                        if !url.ends_with('/') {
+                            self.warning(
+                                span,
+                                format!("ListenBrainz URL `{url}` does not end with `/`"),
+                                Some(format!(
+                                    "API paths are appended to the URL, use `{url}/` instead"
+                                )),
+                            );
+                        }
+                    }
+                    _ => self.error(
+                        span,
+                        format!("invalid ListenBrainz URL `{url}`"),
+                        Some(
+                            "use an HTTP(S) URL like `https://api.listenbrainz.org/1/`".to_owned(),
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    fn check_unknown_keys(&mut self, table: &DeTable, known: &[&str], section: &str, strict: bool) {
+        // Report the keys in the order they appear in the file
+        let mut keys: Vec<_> = table.keys().collect();
+        keys.sort_by_key(|key| key.span().start);
+
+        for key in keys {
+            let name: &str = key.get_ref().as_ref();
+            if known.contains(&name) {
+                continue;
+            }
+
+            let message = format!("unknown config option `{name}`{section}");
+            let help = match suggest(name, known) {
+                Some(suggestion) => Some(format!("did you mean `{suggestion}`?")),
+                None => Some("remove the option if it is not needed".to_owned()),
+            };
+
+            if strict {
+                self.error(Some(key.span()), message, help);
+            } else {
+                self.warning(Some(key.span()), message, help);
+            }
+        }
+
+        if !section.is_empty() {
+            return;
+        }
+
+        if let Some(table) = table
+            .get("normalize")
+            .and_then(|value| value.get_ref().as_table())
+        {
+            self.check_unknown_keys(table, NORMALIZE_KEYS, " in `[normalize]`", strict);
+        }
+        for table in array_tables(table, "player") {
+            self.check_unknown_keys(table, PLAYER_KEYS, " in `[[player]]`", strict);
+        }
+        for table in array_tables(table, "lastfm") {
+            self.check_unknown_keys(table, LASTFM_KEYS, " in `[[lastfm]]`", strict);
+        }
+        for table in array_tables(table, "listenbrainz") {
+            self.check_unknown_keys(table, LISTENBRAINZ_KEYS, " in `[[listenbrainz]]`", strict);
+        }
+    }
+
+    fn check_thresholds(&mut self, table: &DeTable, thresholds: &Thresholds) {
+        if let Some(percent) = thresholds.scrobble_percent
+            && !(1..=100).contains(&percent)
+        {
+            self.error(
+                find(table, &["scrobble-percent"]).map(|(_, _, value)| value),
+                format!("`scrobble-percent` must be between 1 and 100, not {percent}"),
+                None,
+            );
+        }
+
+        if thresholds.min_play_time.is_some()
+            && (thresholds.scrobble_percent.is_some() || thresholds.scrobble_max_seconds.is_some())
+        {
+            self.warning(
+                find(table, &["min-play-time"]).map(|(_, key, _)| key),
+                "`min-play-time` is set, so `scrobble-percent` and `scrobble-max-seconds` \
+                are ignored",
+                Some("remove `min-play-time` to scrobble based on the track length".to_owned()),
+            );
+        }
+    }
+
+    fn check_players(&mut self, table: &DeTable, config: &Config) {
+        self.check_thresholds(table, &config.thresholds);
+
+        if config
+            .stream_title_separators
+            .iter()
+            .flatten()
+            .any(|separator| separator.trim().is_empty())
+        {
+            self.error(
+                find(table, &["stream-title-separators"]).map(|(_, _, value)| value),
+                "`stream-title-separators` contains an empty separator",
+                Some("use separators like `\" - \"` that do not occur in artist names".to_owned()),
+            );
+        }
+
+        let tables = array_tables(table, "player");
+
+        for (i, player) in config.player.iter().flatten().enumerate() {
+            if let Some(table) = tables.get(i) {
+                self.check_thresholds(table, &player.thresholds);
+            }
+        }
+    }
+
+    fn check_filter_script(&mut self, table: &DeTable, config: &Config) {
+        let Some(ref path) = config.filter_script else {
+            return;
+        };
+
+        let span = find(table, &["filter-script"]).map(|(_, _, value)| value);
+
+        match fs::metadata(path) {
+            Ok(metadata) if !metadata.is_file() => self.error(
+                span,
+                format!("filter script {} is not a file", path.display()),
+                None,
+            ),
+            Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => self.error(
+                span,
+                format!("filter script {} is not executable", path.display()),
+                Some(format!(
+                    "make it executable using `chmod +x {}`",
+                    path.display()
+                )),
+            ),
+            Ok(_) => {}
+            Err(err) => self.error(
+                span,
+                format!("cannot access filter script {}: {err}", path.display()),
+                Some("use an absolute path to an existing script".to_owned()),
+            ),
+        }
+    }
+}
+
+/// Get the tables in an array of tables, e.g. `[[listenbrainz]]`.
+fn array_tables<'t>(table: &'t DeTable, name: &str) -> Vec<&'t DeTable<'t>> {
+    table
+        .get(name)
+        .and_then(|value| value.get_ref().as_array())
+        .map(|array| {
+            array
+                .iter()
+                .filter_map(|value| value.get_ref().as_table())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Find the first of the given keys in a table.
+/// Returns the key name, the span of the key and the span of the value.
+fn find<'t>(table: &'t DeTable, names: &[&str]) -> Option<(&'t str, Range<usize>, Range<usize>)> {
+    names.iter().find_map(|name| {
+        table
+            .get_key_value(*name)
+            .map(|(key, value)| (key.get_ref().as_ref(), key.span(), value.span()))
+    })
+}
+
+/// Validate the contents of a config file, including environment variable overrides.
+/// Unknown options are reported as warnings, or as errors if `strict` is true
+/// or the `strict-config` option is enabled.
+///
+/// Returns the (normalized) config if it could be parsed, and all problems that were found.
+pub fn validate(source: &str, strict: bool) -> (Option<Config>, Vec<Diagnostic>) {
+    let mut validator = Validator {
+        source,
+        diagnostics: Vec::new(),
+    };
+
+    let (table, errors) = DeTable::parse_recoverable(source);
+
+    if !errors.is_empty() {
+        for err in errors {
+            validator.error(err.span(), err.message(), None);
+        }
+        return (None, validator.diagnostics);
+    }
+
+    // Check for unknown options before deserializing, to also report typos
+    // if the config turns out to be invalid
+    let strict = strict
+        || table
+            .get_ref()
+            .get("strict-config")
+            .and_then(|value| value.get_ref().as_bool())
+            .unwrap_or(false);
+
+    validator.check_unknown_keys(table.get_ref(), CONFIG_KEYS, "", strict);
+
+    let mut config: Config = match toml::from_str(source) {
+        Ok(config) => config,
+        Err(err) => {
+            validator.error(err.span(), err.message(), None);
+            return (None, validator.diagnostics);
+        }
+    };
+
+    if let Err(err) = override_from_environment(&mut config) {
+        validator.error(None, format!("invalid environment variable: {err:#}"), None);
+        return (None, validator.diagnostics);
+    }
+
+    let table = table.get_ref();
+
+    validator.check_lastfm(table, &config);
+    validator.check_listenbrainz(table, &config);
+    validator.check_players(table, &config);
+    validator.check_filter_script(table, &config);
+
+    config.normalize();
+
+    (Some(config), validator.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use log::LevelFilter;
+    use regex::{Regex, RegexSet};
+    use serde::Serialize;
+
+    use crate::config::secrets::{
+        LastFmAccountKey, LastFmAccountSecret, LastFmKey, LastFmSecret, ListenBrainzGlobalToken,
+        ListenBrainzToken,
+    };
+    use crate::config::tests::ENV_LOCK;
+    use crate::config::{
+        LastFmConfig, ListenBrainzConfig, NormalizeConfig, PlayerConfig, UnknownLength,
+    };
+    use crate::logging::LogFormat;
+    use crate::normalize::JunkPatterns;
+    use crate::secret_service::SessionStore;
+
+    use super::*;
+
+    fn validate(source: &str) -> (Option<Config>, Vec<Diagnostic>) {
+        // Environment variable overrides would affect the results
+        let _guard = ENV_LOCK.lock().unwrap();
+        super::validate(source, false)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let (_, diagnostics) = validate(source);
+        diagnostics
+            .into_iter()
+            .filter(|d| d.severity == Severity::Error)
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_syntax_error_location() {
+        let (config, diagnostics) = validate("min-play-time = 10\nplayer-whitelist = [\"a\"\n");
+
+        assert!(config.is_none());
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].location.as_ref().unwrap().line, 2);
+    }
+
+    #[test]
+    fn test_lastfm_missing_secret() {
+        let (config, diagnostics) = validate("\n  lastfm-key = \"abc\"\n");
+
+        assert!(config.is_some());
+        assert_eq!(diagnostics.len(), 1);
+
+        let location = diagnostics[0].location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (2, 3));
+        assert!(
+            diagnostics[0]
+                .help
+                .as_ref()
+                .unwrap()
+                .contains("lastfm-secret")
+        );
+    }
+
+    #[test]
+    fn test_secrets() {
+        assert_eq!(
+            errors("lastfm-key = \"\"\nlastfm-secret = \"abc\""),
+            vec!["`lastfm-key` is empty"]
+        );
+
+        assert_eq!(
+            errors("lastfm-key-file = \"tests/secret\"\nlastfm-secret-file = \"tests/secret\""),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(
+            errors("[[listenbrainz]]\ntoken-command = \" \""),
+            vec!["`token-command` is empty"]
+        );
+        // Commands are not run during validation
+        assert!(errors("[[listenbrainz]]\ntoken-command = \"false\"").is_empty());
+
+        assert_eq!(
+            errors("lastfm-key-secret-service = \"\"\nlastfm-secret = \"abc\""),
+            vec!["`lastfm-key-secret-service` is empty"]
+        );
+
+        let errors = errors("[[listenbrainz]]\ntoken-file = \"tests/does-not-exist\"");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("cannot read `token-file`"));
+    }
+
+    #[test]
+    fn test_lastfm_accounts() {
+        let (config, diagnostics) = validate(
+            "lastfm-key = \"abc\"\nlastfm-secret = \"def\"\n\
+            [[lastfm]]\nusername = \"alice\"\nkey = \"abc\"\nsecret-file = \"tests/does-not-exist\"\n\
+            [[lastfm]]\nuser = \"bob\"\nkey = \"abc\"\nsecret = \"def\"\n",
+        );
+
+        assert_eq!(config.unwrap().lastfm.unwrap().len(), 2);
+
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.location.as_ref().unwrap().line))
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Warning, 8),
+                (Severity::Warning, 1),
+                (Severity::Error, 6),
+            ]
+        );
+        assert!(diagnostics[0].message.contains("`user` in `[[lastfm]]`"));
+    }
+
+    #[test]
+    fn test_listenbrainz_url() {
+        assert_eq!(
+            errors("[[listenbrainz]]\ntoken = \"abc\"\nurl = \"localhost:8080\""),
+            vec!["invalid ListenBrainz URL `localhost:8080`"]
+        );
+
+        let (_, diagnostics) =
+            validate("[[listenbrainz]]\ntoken = \"abc\"\nurl = \"https://example.com/1\"");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].location.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_filter_script() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("filter.sh");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+
+        let source = format!("filter-script = {:?}", path.to_str().unwrap());
+
+        assert_eq!(
+            errors(&source),
+            vec![format!(
+                "filter script {} is not executable",
+                path.display()
+            )]
+        );
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(errors(&source).is_empty());
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let (_, diagnostics) = validate(
+            "scrobble-percent = 60\n\
+            [[player]]\nname = \"firefox\"\nmin-track-length = 60\nscrobble-percent = 101\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "`scrobble-percent` must be between 1 and 100, not 101"
+        );
+        assert_eq!(diagnostics[0].location.as_ref().unwrap().line, 5);
+
+        let (config, diagnostics) = validate(
+            "min-play-time = 10\nscrobble-max-seconds = 120\n\
+            [[player]]\nname = \"^mpv$\"\nscrobble-percent = 90\n",
+        );
+        let config = config.unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+
+        let player = &config.player.as_ref().unwrap()[0];
+        assert!(player.name.is_match("mpv"));
+        assert_eq!(player.thresholds.scrobble_percent, Some(90));
+        assert_eq!(
+            player.thresholds.or(&config.thresholds),
+            Thresholds {
+                scrobble_percent: Some(90),
+                scrobble_max_seconds: Some(Duration::from_secs(120)),
+                ..Default::default()
+            }
+        );
+
+        // A player's own `min-play-time` still takes precedence
+        let player = Thresholds {
+            min_play_time: Some(Duration::from_secs(20)),
+            scrobble_percent: Some(90),
+            ..Default::default()
+        };
+        assert_eq!(
+            player.or(&config.thresholds).min_play_time,
+            Some(Duration::from_secs(20))
+        );
+
+        assert_eq!(errors("[[player]]\nname = \"(\"").len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let source = "player-whitlist = []\nmin-playtime = 10\nfoo = 1\n\
+            [[listenbrainz]]\ntoken = \"abc\"\nurls = \"https://example.com/\"\n";
+
+        let (config, diagnostics) = validate(source);
+        assert!(config.is_some());
+
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.message.as_str(), d.help.as_deref().unwrap()))
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    Severity::Warning,
+                    "unknown config option `player-whitlist`",
+                    "did you mean `player-whitelist`?"
+                ),
+                (
+                    Severity::Warning,
+                    "unknown config option `min-playtime`",
+                    "did you mean `min-play-time`?"
+                ),
+                (
+                    Severity::Warning,
+                    "unknown config option `foo`",
+                    "remove the option if it is not needed"
+                ),
+                (
+                    Severity::Warning,
+                    "unknown config option `urls` in `[[listenbrainz]]`",
+                    "did you mean `url`?"
+                ),
+            ]
+        );
+
+        let location = diagnostics[1].location.as_ref().unwrap();
+        assert_eq!((location.line, location.column), (2, 1));
+
+        // In strict mode, unknown options are errors
+        let (config, _) = validate("strict-config = true\nmin-playtime = 10");
+        assert!(config.is_some());
+        assert_eq!(
+            errors("strict-config = true\nmin-playtime = 10"),
+            vec!["unknown config option `min-playtime`"]
+        );
+
+        let _guard = ENV_LOCK.lock().unwrap();
+        let (_, diagnostics) = super::validate("min-playtime = 10", true);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    /// The keys that a value is serialized with.
+    fn serialized_keys(value: impl Serialize) -> BTreeSet<String> {
+        serde_json::to_value(value)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// The keys of each way of specifying a secret, including aliases.
+    fn secret_keys<T: Serialize>(
+        option: &SecretOption,
+        variants: [fn(String) -> T; 4],
+    ) -> BTreeSet<String> {
+        let keys: BTreeSet<_> = variants
+            .into_iter()
+            .flat_map(|variant| serialized_keys(variant(String::new())))
+            .collect();
+
+        assert_eq!(
+            keys,
+            key_set(&[
+                option.inline[0],
+                option.file,
+                option.command,
+                option.secret_service
+            ])
+        );
+
+        keys.into_iter()
+            .chain(option.inline.iter().map(|alias| alias.to_string()))
+            .collect()
+    }
+
+    fn key_set(keys: &[&str]) -> BTreeSet<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_key_lists() {
+        // Every field is set, so that all keys are serialized
+        let thresholds = Thresholds {
+            min_play_time: Some(Duration::from_secs(1)),
+            min_track_length: Some(Duration::from_secs(1)),
+            scrobble_percent: Some(50),
+            scrobble_max_seconds: Some(Duration::from_secs(1)),
+            unknown_length_play_time: Some(Duration::from_secs(1)),
+            unknown_length: Some(UnknownLength::Never),
+        };
+        let player = PlayerConfig {
+            name: Regex::new("mpv").unwrap(),
+            stream: Some(true),
+            normalize: Some(true),
+            thresholds: thresholds.clone(),
+        };
+        let normalize = NormalizeConfig {
+            split_artist: Some(true),
+            strip_junk: Some(true),
+            junk_patterns: Some(JunkPatterns::new(vec!["live".to_owned()]).unwrap()),
+            strip_channel_suffixes: Some(true),
+            channel_suffixes: Some(vec!["VEVO".to_owned()]),
+        };
+        let lastfm = LastFmConfig {
+            username: Some("alice".to_owned()),
+            key: LastFmAccountKey::default(),
+            secret: LastFmAccountSecret::default(),
+        };
+        let listenbrainz = ListenBrainzConfig {
+            url: Some("https://api.listenbrainz.org".to_owned()),
+            token: ListenBrainzToken::default(),
+        };
+        let regex_set = || Some(RegexSet::new(["mpv"]).unwrap());
+
+        let lastfm_keys: BTreeSet<_> = serialized_keys(&lastfm)
+            .into_iter()
+            .chain(secret_keys(
+                &LASTFM_ACCOUNT_KEY,
+                [
+                    LastFmAccountKey::Inline,
+                    LastFmAccountKey::File,
+                    LastFmAccountKey::Command,
+                    LastFmAccountKey::SecretService,
+                ],
+            ))
+            .chain(secret_keys(
+                &LASTFM_ACCOUNT_SECRET,
+                [
+                    LastFmAccountSecret::Inline,
+                    LastFmAccountSecret::File,
+                    LastFmAccountSecret::Command,
+                    LastFmAccountSecret::SecretService,
+                ],
+            ))
+            .collect();
+        assert_eq!(lastfm_keys, key_set(LASTFM_KEYS));
+
+        let listenbrainz_keys: BTreeSet<_> = serialized_keys(&listenbrainz)
+            .into_iter()
+            .chain(secret_keys(
+                &LISTENBRAINZ_TOKEN,
+                [
+                    ListenBrainzToken::Inline,
+                    ListenBrainzToken::File,
+                    ListenBrainzToken::Command,
+                    ListenBrainzToken::SecretService,
+                ],
+            ))
+            .collect();
+        assert_eq!(listenbrainz_keys, key_set(LISTENBRAINZ_KEYS));
+
+        assert_eq!(serialized_keys(&player), key_set(PLAYER_KEYS));
+        assert_eq!(serialized_keys(&normalize), key_set(NORMALIZE_KEYS));
+
+        // The secrets are flattened, so they are added separately
+        let config = Config {
+            lastfm_key: None,
+            lastfm_secret: None,
+            lastfm_username: Some("alice".to_owned()),
+            session_store: Some(SessionStore::File),
+            listenbrainz_token: None,
+            thresholds,
+            player_whitelist: regex_set(),
+            player_ignorelist: regex_set(),
+            filter_script: Some(PathBuf::from("filter.sh")),
+            stream_title_separators: Some(vec![" - ".to_owned()]),
+            use_track_start_timestamp: Some(true),
+            watch_config: Some(true),
+            scrobble_on_exit: Some(true),
+            log_level: Some(LevelFilter::Info),
+            log_format: Some(LogFormat::Text),
+            strict_config: Some(true),
+            normalize: Some(normalize),
+            player: Some(vec![player]),
+            lastfm: Some(vec![lastfm]),
+            listenbrainz: Some(vec![listenbrainz]),
+        };
+        let config_keys: BTreeSet<_> = serialized_keys(&config)
+            .into_iter()
+            .chain(secret_keys(
+                &LASTFM_KEY,
+                [
+                    LastFmKey::Inline,
+                    LastFmKey::File,
+                    LastFmKey::Command,
+                    LastFmKey::SecretService,
+                ],
+            ))
+            .chain(secret_keys(
+                &LASTFM_SECRET,
+                [
+                    LastFmSecret::Inline,
+                    LastFmSecret::File,
+                    LastFmSecret::Command,
+                    LastFmSecret::SecretService,
+                ],
+            ))
+            .chain(secret_keys(
+                &LISTENBRAINZ_GLOBAL_TOKEN,
+                [
+                    ListenBrainzGlobalToken::Inline,
+                    ListenBrainzGlobalToken::File,
+                    ListenBrainzGlobalToken::Command,
+                    ListenBrainzGlobalToken::SecretService,
+                ],
+            ))
+            .collect();
+        assert_eq!(config_keys, key_set(CONFIG_KEYS));
+    }
+
+    #[test]
+    fn test_known_keys() {
+        // All options in the template should be known
+        let template = Config::template().replace("# ", "");
+        let table = DeTable::parse(&template).unwrap();
+
+        for (key, value) in table.get_ref().iter() {
+            assert!(CONFIG_KEYS.contains(&key.get_ref().as_ref()));
+
+            if key.get_ref() == "listenbrainz" {
+                let array = value.get_ref().as_array().unwrap();
+                for key in array[0].get_ref().as_table().unwrap().keys() {
+                    assert!(LISTENBRAINZ_KEYS.contains(&key.get_ref().as_ref()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let (_, diagnostics) = validate("lastfm-secret = \"abc\"");
+
+        assert_eq!(
+            diagnostics[0].render(Path::new("config.toml")),
+            "error: `lastfm-secret` is set, but `lastfm-key` is missing\n \
+            --> config.toml:1:1\n  \
+            |\n\
+            1 | lastfm-secret = \"abc\"\n  \
+            | ^^^^^^^^^^^^^\n  \
+            = help: add `lastfm-key = \"<API key>\"` (or `lastfm-key-file`), \
+            or remove `lastfm-secret` to disable Last.fm"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone};
+
+const DATE_TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+];
+
+/// Parse a point in time given on the command line, relative to `now`.
+///
+/// Accepts dates (`2026-10-01`), dates with a time (`2026-10-01 18:00`), RFC 3339 timestamps,
+/// `today`, `yesterday`, and durations before now (`30m`, `12h`, `3d`, `2w`).
+/// If `end_of_day` is set, a date without a time refers to the end of that day instead of the start.
+fn parse_at(value: &str, end_of_day: bool, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let value = value.trim();
+
+    let date = match value {
+        "today" => Some(now.date_naive()),
+        "yesterday" => now.date_naive().pred_opt(),
+        _ => NaiveDate::parse_from_str(value, "%Y-%m-%d").ok(),
+    };
+
+    if let Some(date) = date {
+        let date = if end_of_day {
+            date.checked_add_days(Days::new(1))
+                .ok_or_else(|| anyhow!("Date out of range: {value}"))?
+        } else {
+            date
+        };
+
+        return local(date.and_time(Default::default()));
+    }
+
+    if let Some(datetime) = DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    {
+        return local(datetime);
+    }
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.with_timezone(&Local));
+    }
+
+    if let Some(unit) = value.chars().last()
+        && let Ok(amount) = value[..value.len() - unit.len_utf8()].trim().parse::<i64>()
+    {
+        let delta = match unit {
+            's' => TimeDelta::try_seconds(amount),
+            'm' => TimeDelta::try_minutes(amount),
+            'h' => TimeDelta::try_hours(amount),
+            'd' => TimeDelta::try_days(amount),
+            'w' => TimeDelta::try_weeks(amount),
+            _ => bail!("Unknown time unit `{unit}` in {value} (use s, m, h, d or w)"),
+        };
+
+        return delta
+            .and_then(|delta| now.checked_sub_signed(delta))
+            .ok_or_else(|| anyhow!("Time out of range: {value}"));
+    }
+
+    bail!("Invalid time: {value} (use e.g. 2026-10-01, \"2026-10-01 18:00\", yesterday or 3d)")
+}
+
+fn local(datetime: NaiveDateTime) -> Result<DateTime<Local>> {
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .ok_or_else(|| anyhow!("{datetime} does not exist in the local time zone"))
+}
+
+/// Parse the start of a period given on the command line.
+pub fn parse_since(value: &str) -> Result<DateTime<Local>> {
+    parse_at(value, false, Local::now())
+}
+
+/// Parse the end of a period given on the command line. A date includes the whole day.
+pub fn parse_until(value: &str) -> Result<DateTime<Local>> {
+    parse_at(value, true, Local::now())
+}
+
+/// Convert seconds since the UNIX epoch to local time.
+pub fn from_timestamp(timestamp: u64) -> DateTime<Local> {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+}
+
+/// Convert a time to seconds since the UNIX epoch.
+pub fn to_timestamp(time: DateTime<Local>) -> u64 {
+    time.timestamp().max(0) as u64
+}
+
+/// Format a duration as `m:ss`, or `h:mm:ss` if it is an hour or longer.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let now = Local.with_ymd_and_hms(2026, 10, 18, 20, 30, 0).unwrap();
+        let parse = |value, end_of_day| parse_at(value, end_of_day, now).unwrap();
+
+        let midnight = Local.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        assert_eq!(parse("2026-10-01", false), midnight);
+        assert_eq!(
+            parse("2026-10-01", true),
+            Local.with_ymd_and_hms(2026, 10, 2, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse("2026-10-01 18:00", true),
+            Local.with_ymd_and_hms(2026, 10, 1, 18, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse("2026-10-01T00:00:00Z", false),
+            DateTime::from_timestamp(1_790_812_800, 0).unwrap()
+        );
+
+        assert_eq!(
+            parse("yesterday", false),
+            Local.with_ymd_and_hms(2026, 10, 17, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse("today", true),
+            Local.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap()
+        );
+
+        assert_eq!(parse("3d", false), now - TimeDelta::days(3));
+        assert_eq!(parse("12 h", false), now - TimeDelta::hours(12));
+
+        assert!(parse_at("3y", false, now).is_err());
+        assert!(parse_at("last week", false, now).is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(5)), "0:05");
+        assert_eq!(format_duration(Duration::from_secs(245)), "4:05");
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 3600 + 61)),
+            "3:01:01"
+        );
+    }
+}
@@ -19,6 +19,8 @@ use std::process::{Command, Stdio};
 
 use anyhow::{Context, Result, anyhow, bail};
 
+use log::{debug, trace};
+
 use mpris::Metadata;
 
 use crate::config::Config;
@@ -38,6 +40,8 @@ pub fn filter_metadata(config: &Config, track: Track, metadata: &Metadata) -> Re
 
     let path = config.filter_script.as_ref().unwrap();
 
+    debug!("Running filter script {}", path.display());
+
     let mut child = Command::new(config.filter_script.as_ref().unwrap())
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -93,6 +97,8 @@ pub fn filter_metadata(config: &Config, track: Track, metadata: &Metadata) -> Re
     let output =
         String::from_utf8(output.stdout).context("Filter script stdout is not valid UTF-8")?;
 
+    trace!("Filter script output: {output:?}");
+
     let mut output = output.split('\n');
     match (output.next(), output.next(), output.next()) {
         (Some(artist), Some(title), album) => {
@@ -0,0 +1,970 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The listening history database, which records every track that was played
+//! and what happened when it was submitted to each service.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, params, params_from_iter};
+
+use crate::state::{self, unix_timestamp};
+use crate::track::Track;
+
+pub mod export;
+pub mod import;
+pub mod list;
+pub mod stats;
+
+const HISTORY_DIR: &str = "rescrobbled";
+const HISTORY_FILE: &str = "history.sqlite3";
+
+/// How long to wait for another process (e.g. `rescrobbled history`) to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Statements that upgrade the database from the previous schema version, in order.
+/// The index of an entry plus one is the schema version it results in.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE plays (
+        id INTEGER PRIMARY KEY,
+        artist TEXT NOT NULL,
+        title TEXT NOT NULL,
+        album TEXT,
+        player TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        play_time INTEGER NOT NULL,
+        length INTEGER,
+        decision TEXT NOT NULL
+    );
+    CREATE INDEX plays_started_at ON plays (started_at);
+
+    CREATE TABLE submissions (
+        play_id INTEGER NOT NULL REFERENCES plays (id) ON DELETE CASCADE,
+        service TEXT NOT NULL,
+        service_name TEXT NOT NULL,
+        status TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        attempts INTEGER NOT NULL,
+        error TEXT,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (play_id, service)
+    );
+    CREATE INDEX submissions_status ON submissions (status, service);
+",
+    "
+    ALTER TABLE submissions ADD COLUMN corrected_artist TEXT;
+    ALTER TABLE submissions ADD COLUMN corrected_title TEXT;
+    ALTER TABLE submissions ADD COLUMN corrected_album TEXT;
+",
+];
+
+/// What was decided about a track that was played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The track played long enough and was submitted.
+    Scrobbled,
+    /// The track played long enough, but the filter script ignored it.
+    Ignored,
+    /// The track stopped playing before it could be scrobbled.
+    Skipped,
+}
+
+impl Decision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Scrobbled => "scrobbled",
+            Self::Ignored => "ignored",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+impl ToSql for Decision {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Decision {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "scrobbled" => Ok(Self::Scrobbled),
+            "ignored" => Ok(Self::Ignored),
+            "skipped" => Ok(Self::Skipped),
+            other => Err(FromSqlError::Other(
+                anyhow!("Invalid decision: {other}").into(),
+            )),
+        }
+    }
+}
+
+/// The status of a submission to a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Submitted,
+    /// The submission failed, and will be retried.
+    Pending,
+    /// The service rejected the submission, so it is not retried.
+    Rejected,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Submitted => "submitted",
+            Self::Pending => "pending",
+            Self::Rejected => "rejected",
+        }
+    }
+}
+
+impl ToSql for Status {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Status {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "submitted" => Ok(Self::Submitted),
+            "pending" => Ok(Self::Pending),
+            "rejected" => Ok(Self::Rejected),
+            other => Err(FromSqlError::Other(
+                anyhow!("Invalid submission status: {other}").into(),
+            )),
+        }
+    }
+}
+
+/// A track that was played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Play {
+    pub track: Track,
+    /// The D-Bus bus name of the player.
+    pub player: String,
+    /// Seconds since the UNIX epoch at which the track started playing.
+    pub started_at: u64,
+    /// How long the track had played when the decision was made.
+    pub play_time: Duration,
+    pub length: Option<Duration>,
+    pub decision: Decision,
+}
+
+/// A scrobble that could not be submitted to a service yet.
+#[derive(Debug, PartialEq)]
+pub struct PendingScrobble {
+    /// The ID of the play in the history.
+    pub play: i64,
+    pub track: Track,
+    /// Seconds since the UNIX epoch, the timestamp to submit the scrobble with.
+    pub timestamp: u64,
+}
+
+impl PendingScrobble {
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.timestamp)
+    }
+}
+
+/// The outcome of submitting a play to a service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submission {
+    /// The name of the service, as displayed by `Service` when the play was submitted.
+    pub service: String,
+    pub status: Status,
+    pub attempts: u32,
+    /// The error of the last failed attempt, if the submission is pending or rejected.
+    pub error: Option<String>,
+    /// The track as the service recorded it, if it corrected the artist, title or album.
+    pub corrected: Option<Track>,
+}
+
+/// A play in the history, with its submissions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub id: i64,
+    pub play: Play,
+    pub submissions: Vec<Submission>,
+}
+
+/// Which plays to get from the history.
+#[derive(Debug, Default)]
+pub struct Query {
+    /// Only plays that started at or after this time, in seconds since the UNIX epoch.
+    pub since: Option<u64>,
+    /// Only plays that started before this time, in seconds since the UNIX epoch.
+    pub until: Option<u64>,
+    /// Only plays whose artist contains this text, ignoring case.
+    pub artist: Option<String>,
+    /// Only plays whose player bus name contains this text, ignoring case.
+    pub player: Option<String>,
+    /// Only plays that were submitted to a service whose name contains this text, ignoring case.
+    pub service: Option<String>,
+    /// Only plays with a failed (pending or rejected) submission.
+    pub failed: bool,
+    /// Only plays with this decision.
+    pub decision: Option<Decision>,
+    /// Only the most recent plays.
+    pub limit: Option<usize>,
+    /// Don't load the submissions of the plays, if only the plays themselves are needed.
+    pub without_submissions: bool,
+}
+
+/// Make a `LIKE` pattern that matches values containing `text`.
+fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{escaped}%")
+}
+
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    /// Open the history database in the user data directory, creating it if necessary.
+    pub fn open() -> Result<Self> {
+        Self::open_at(&history_path()?)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .context("Failed to configure history database")?;
+
+        Self::init(connection)
+    }
+
+    /// Open a history database that is not saved to disk.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().context("Failed to create history database")?)
+    }
+
+    fn init(mut connection: Connection) -> Result<Self> {
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .and_then(|()| connection.pragma_update(None, "foreign_keys", true))
+            .context("Failed to configure history database")?;
+
+        migrate(&mut connection).context("Failed to update history database")?;
+
+        Ok(Self { connection })
+    }
+
+    /// Record a play, returning its ID.
+    pub fn add_play(&self, play: &Play) -> Result<i64> {
+        self.connection
+            .execute(
+                "INSERT INTO plays
+                    (artist, title, album, player, started_at, play_time, length, decision)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    play.track.artist(),
+                    play.track.title(),
+                    play.track.album(),
+                    play.player,
+                    play.started_at,
+                    play.play_time.as_millis() as u64,
+                    play.length.map(|length| length.as_millis() as u64),
+                    play.decision,
+                ],
+            )
+            .context("Failed to add play to history")?;
+
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Record the outcome of submitting a play to a service, given the identifier
+    /// and the name of the service. Pending submissions are retried later.
+    pub fn add_submission(
+        &self,
+        play: i64,
+        service: &str,
+        service_name: &str,
+        timestamp: u64,
+        status: Status,
+        error: Option<&anyhow::Error>,
+    ) -> Result<()> {
+        // A pending submission without an error was not attempted yet,
+        // e.g. because the service is unavailable
+        let attempts = if status == Status::Pending && error.is_none() {
+            0
+        } else {
+            1
+        };
+
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO submissions
+                    (play_id, service, service_name, status, timestamp, attempts, error, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    play,
+                    service,
+                    service_name,
+                    status,
+                    timestamp,
+                    attempts,
+                    error.map(|err| format!("{err:#}")),
+                    now(),
+                ],
+            )
+            .context("Failed to add submission to history")?;
+
+        Ok(())
+    }
+
+    /// Determine if a track was scrobbled within `window` of the given time.
+    pub fn contains(&self, track: &Track, timestamp: u64, window: Duration) -> Result<bool> {
+        let window = window.as_secs();
+
+        self.connection
+            .query_row(
+                "SELECT EXISTS (
+                    SELECT 1 FROM plays
+                    WHERE artist = ? COLLATE NOCASE AND title = ? COLLATE NOCASE
+                    AND decision = ? AND started_at BETWEEN ? AND ?
+                )",
+                params![
+                    track.artist(),
+                    track.title(),
+                    Decision::Scrobbled,
+                    timestamp.saturating_sub(window),
+                    timestamp.saturating_add(window),
+                ],
+                |row| row.get(0),
+            )
+            .context("Failed to query history")
+    }
+
+    /// Get the pending scrobbles for a service, oldest first.
+    pub fn pending(&self, service: &str) -> Result<Vec<PendingScrobble>> {
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT plays.id, artist, title, album, timestamp
+                FROM submissions JOIN plays ON plays.id = play_id
+                WHERE service = ? AND status = ?
+                ORDER BY timestamp, plays.id",
+            )
+            .context("Failed to query pending scrobbles")?;
+
+        let pending = statement
+            .query_map(params![service, Status::Pending], |row| {
+                Ok(PendingScrobble {
+                    play: row.get(0)?,
+                    track: Track::new(
+                        &row.get::<_, String>(1)?,
+                        &row.get::<_, String>(2)?,
+                        row.get::<_, Option<String>>(3)?.as_deref(),
+                    ),
+                    timestamp: row.get(4)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .context("Failed to query pending scrobbles")?;
+
+        Ok(pending)
+    }
+
+    /// Mark a pending scrobble as submitted.
+    pub fn mark_submitted(&self, play: i64, service: &str) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE submissions
+                SET status = ?, attempts = attempts + 1, error = NULL, updated_at = ?
+                WHERE play_id = ? AND service = ?",
+                params![Status::Submitted, now(), play, service],
+            )
+            .context("Failed to update submission in history")?;
+
+        Ok(())
+    }
+
+    /// Record another failed attempt to submit a pending scrobble.
+    pub fn mark_failed(&self, play: i64, service: &str, error: &anyhow::Error) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE submissions
+                SET attempts = attempts + 1, error = ?, updated_at = ?
+                WHERE play_id = ? AND service = ?",
+                params![format!("{error:#}"), now(), play, service],
+            )
+            .context("Failed to update submission in history")?;
+
+        Ok(())
+    }
+
+    /// Record how the service corrected a submitted scrobble.
+    pub fn set_correction(&self, play: i64, service: &str, corrected: &Track) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE submissions
+                SET corrected_artist = ?, corrected_title = ?, corrected_album = ?
+                WHERE play_id = ? AND service = ?",
+                params![
+                    corrected.artist(),
+                    corrected.title(),
+                    corrected.album(),
+                    play,
+                    service
+                ],
+            )
+            .context("Failed to update submission in history")?;
+
+        Ok(())
+    }
+
+    /// Record that the service rejected a pending scrobble, so it is no longer retried.
+    pub fn mark_rejected(&self, play: i64, service: &str, error: &anyhow::Error) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE submissions
+                SET status = ?, attempts = attempts + 1, error = ?, updated_at = ?
+                WHERE play_id = ? AND service = ?",
+                params![Status::Rejected, format!("{error:#}"), now(), play, service],
+            )
+            .context("Failed to update submission in history")?;
+
+        Ok(())
+    }
+
+    /// Get the plays matching a query, oldest first.
+    pub fn entries(&self, query: &Query) -> Result<Vec<Entry>> {
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(since) = query.since {
+            conditions.push("started_at >= ?");
+            params.push(Box::new(since));
+        }
+        if let Some(until) = query.until {
+            conditions.push("started_at < ?");
+            params.push(Box::new(until));
+        }
+        if let Some(ref artist) = query.artist {
+            conditions.push("artist LIKE ? ESCAPE '\\'");
+            params.push(Box::new(contains_pattern(artist)));
+        }
+        if let Some(ref player) = query.player {
+            conditions.push("player LIKE ? ESCAPE '\\'");
+            params.push(Box::new(contains_pattern(player)));
+        }
+        match (&query.service, query.failed) {
+            (Some(service), failed) => {
+                conditions.push(if failed {
+                    "EXISTS (SELECT 1 FROM submissions WHERE play_id = plays.id \
+                    AND service_name LIKE ? ESCAPE '\\' AND status IN ('pending', 'rejected'))"
+                } else {
+                    "EXISTS (SELECT 1 FROM submissions WHERE play_id = plays.id \
+                    AND service_name LIKE ? ESCAPE '\\')"
+                });
+                params.push(Box::new(contains_pattern(service)));
+            }
+            (None, true) => conditions.push(
+                "EXISTS (SELECT 1 FROM submissions WHERE play_id = plays.id \
+                AND status IN ('pending', 'rejected'))",
+            ),
+            (None, false) => {}
+        }
+        if let Some(decision) = query.decision {
+            conditions.push("decision = ?");
+            params.push(Box::new(decision));
+        }
+
+        let mut sql =
+            "SELECT id, artist, title, album, player, started_at, play_time, length, decision \
+            FROM plays"
+                .to_owned();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY started_at DESC, id DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(&sql)
+            .context("Failed to query history")?;
+
+        let mut entries = statement
+            .query_map(params_from_iter(&params), |row| {
+                Ok(Entry {
+                    id: row.get(0)?,
+                    play: Play {
+                        track: Track::new(
+                            &row.get::<_, String>(1)?,
+                            &row.get::<_, String>(2)?,
+                            row.get::<_, Option<String>>(3)?.as_deref(),
+                        ),
+                        player: row.get(4)?,
+                        started_at: row.get(5)?,
+                        play_time: Duration::from_millis(row.get(6)?),
+                        length: row.get::<_, Option<u64>>(7)?.map(Duration::from_millis),
+                        decision: row.get(8)?,
+                    },
+                    submissions: Vec::new(),
+                })
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .context("Failed to query history")?;
+
+        entries.reverse();
+
+        if !query.without_submissions {
+            for entry in &mut entries {
+                entry.submissions = self.submissions(entry.id)?;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn submissions(&self, play: i64) -> Result<Vec<Submission>> {
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT service_name, status, attempts, error,
+                    corrected_artist, corrected_title, corrected_album
+                FROM submissions
+                WHERE play_id = ? ORDER BY service_name",
+            )
+            .context("Failed to query submissions")?;
+
+        let submissions = statement
+            .query_map([play], |row| {
+                let corrected = match (
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ) {
+                    (Some(artist), Some(title)) => Some(Track::new(
+                        &artist,
+                        &title,
+                        row.get::<_, Option<String>>(6)?.as_deref(),
+                    )),
+                    _ => None,
+                };
+
+                Ok(Submission {
+                    service: row.get(0)?,
+                    status: row.get(1)?,
+                    attempts: row.get(2)?,
+                    error: row.get(3)?,
+                    corrected,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .context("Failed to query submissions")?;
+
+        Ok(submissions)
+    }
+
+    /// Move the pending scrobbles that were saved in the state file, because they could
+    /// not be added to the history.
+    pub fn import_pending(&mut self, pending: &[state::PendingScrobble]) -> Result<()> {
+        let transaction = self
+            .connection
+            .transaction()
+            .context("Failed to import pending scrobbles")?;
+
+        for scrobble in pending {
+            transaction
+                .execute(
+                    "INSERT INTO plays
+                        (artist, title, album, player, started_at, play_time, decision)
+                    VALUES (?, ?, ?, '', ?, 0, ?)",
+                    params![
+                        scrobble.track.artist(),
+                        scrobble.track.title(),
+                        scrobble.track.album(),
+                        scrobble.timestamp,
+                        Decision::Scrobbled,
+                    ],
+                )
+                .and_then(|_| {
+                    transaction.execute(
+                        "INSERT INTO submissions
+                            (play_id, service, service_name, status, timestamp, attempts, updated_at)
+                        VALUES (?, ?, ?, ?, ?, 1, ?)",
+                        params![
+                            transaction.last_insert_rowid(),
+                            scrobble.service,
+                            scrobble.service_name,
+                            Status::Pending,
+                            scrobble.timestamp,
+                            now(),
+                        ],
+                    )
+                })
+                .context("Failed to import pending scrobbles")?;
+        }
+
+        transaction
+            .commit()
+            .context("Failed to import pending scrobbles")
+    }
+}
+
+/// Bring the database schema up to date.
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "The history database was created by a newer version of rescrobbled"
+        ));
+    }
+
+    let transaction = connection.transaction()?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+fn now() -> u64 {
+    unix_timestamp(&SystemTime::now())
+}
+
+fn history_path() -> Result<PathBuf> {
+    let mut path = dirs::data_dir().ok_or_else(|| anyhow!("User data directory does not exist"))?;
+
+    path.push(HISTORY_DIR);
+
+    if !path.exists() {
+        fs::create_dir_all(&path).context("Failed to create data directory")?;
+    }
+
+    path.push(HISTORY_FILE);
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(artist: &str, title: &str, started_at: u64, decision: Decision) -> Play {
+        Play {
+            track: Track::new(artist, title, None),
+            player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+            started_at,
+            play_time: Duration::from_secs(100),
+            length: Some(Duration::from_secs(200)),
+            decision,
+        }
+    }
+
+    #[test]
+    fn test_pending() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(HISTORY_FILE);
+
+        let history = History::open_at(&path).unwrap();
+
+        let first = history
+            .add_play(&play(
+                "Boards of Canada",
+                "Roygbiv",
+                1000,
+                Decision::Scrobbled,
+            ))
+            .unwrap();
+        let second = history
+            .add_play(&play(
+                "Boards of Canada",
+                "Olson",
+                2000,
+                Decision::Scrobbled,
+            ))
+            .unwrap();
+        history
+            .add_play(&play(
+                "Boards of Canada",
+                "Aquarius",
+                3000,
+                Decision::Skipped,
+            ))
+            .unwrap();
+
+        let err = anyhow!("Network is unreachable");
+        history
+            .add_submission(
+                second,
+                "lastfm:key",
+                "Last.fm",
+                2000,
+                Status::Pending,
+                Some(&err),
+            )
+            .unwrap();
+        history
+            .add_submission(
+                first,
+                "lastfm:key",
+                "Last.fm",
+                1000,
+                Status::Pending,
+                Some(&err),
+            )
+            .unwrap();
+        history
+            .add_submission(
+                first,
+                "listenbrainz",
+                "ListenBrainz",
+                1000,
+                Status::Submitted,
+                None,
+            )
+            .unwrap();
+
+        let pending = history.pending("lastfm:key").unwrap();
+        assert_eq!(
+            pending,
+            vec![
+                PendingScrobble {
+                    play: first,
+                    track: Track::new("Boards of Canada", "Roygbiv", None),
+                    timestamp: 1000,
+                },
+                PendingScrobble {
+                    play: second,
+                    track: Track::new("Boards of Canada", "Olson", None),
+                    timestamp: 2000,
+                },
+            ]
+        );
+        assert!(history.pending("listenbrainz").unwrap().is_empty());
+
+        history.mark_failed(first, "lastfm:key", &err).unwrap();
+        history.mark_submitted(second, "lastfm:key").unwrap();
+
+        // Pending scrobbles are kept when the database is opened again
+        drop(history);
+        let history = History::open_at(&path).unwrap();
+
+        let pending = history.pending("lastfm:key").unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].play, first);
+
+        let (attempts, error): (u32, String) = history
+            .connection
+            .query_row(
+                "SELECT attempts, error FROM submissions WHERE play_id = ? AND service = ?",
+                params![first, "lastfm:key"],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(error, "Network is unreachable");
+
+        // Rejected scrobbles are not retried
+        history
+            .mark_rejected(first, "lastfm:key", &anyhow!("Invalid parameters"))
+            .unwrap();
+        assert!(history.pending("lastfm:key").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_entries() {
+        let history = History::open_in_memory().unwrap();
+
+        let mut plays = [
+            play("Aphex Twin", "Xtal", 1000, Decision::Scrobbled),
+            play("Aphex_Twin", "Tha", 2000, Decision::Scrobbled),
+            play("Autechre", "Bike", 3000, Decision::Skipped),
+        ];
+        plays[2].player = "org.mpris.MediaPlayer2.spotify".to_owned();
+
+        let ids: Vec<_> = plays
+            .iter()
+            .map(|play| history.add_play(play).unwrap())
+            .collect();
+
+        let err = anyhow!("Network is unreachable");
+        history
+            .add_submission(
+                ids[0],
+                "lastfm:key:alice",
+                "Last.fm (alice)",
+                1000,
+                Status::Submitted,
+                None,
+            )
+            .unwrap();
+        history
+            .add_submission(
+                ids[0],
+                "listenbrainz",
+                "ListenBrainz",
+                1000,
+                Status::Pending,
+                Some(&err),
+            )
+            .unwrap();
+        history
+            .add_submission(
+                ids[1],
+                "lastfm:key:alice",
+                "Last.fm (alice)",
+                2000,
+                Status::Rejected,
+                Some(&anyhow!("Invalid parameters")),
+            )
+            .unwrap();
+
+        let query = |query: Query| -> Vec<i64> {
+            history
+                .entries(&query)
+                .unwrap()
+                .iter()
+                .map(|entry| entry.id)
+                .collect()
+        };
+
+        assert_eq!(query(Query::default()), ids);
+        assert_eq!(
+            query(Query {
+                limit: Some(2),
+                ..Default::default()
+            }),
+            &ids[1..]
+        );
+        assert_eq!(
+            query(Query {
+                since: Some(1000),
+                until: Some(3000),
+                ..Default::default()
+            }),
+            &ids[..2]
+        );
+        assert_eq!(
+            query(Query {
+                artist: Some("aphex twin".to_owned()),
+                ..Default::default()
+            }),
+            &ids[..1]
+        );
+        assert_eq!(
+            query(Query {
+                player: Some("Spotify".to_owned()),
+                ..Default::default()
+            }),
+            &ids[2..]
+        );
+        assert_eq!(
+            query(Query {
+                failed: true,
+                ..Default::default()
+            }),
+            &ids[..2]
+        );
+        assert_eq!(
+            query(Query {
+                decision: Some(Decision::Skipped),
+                ..Default::default()
+            }),
+            &ids[2..]
+        );
+        assert_eq!(
+            query(Query {
+                service: Some("last.fm".to_owned()),
+                failed: true,
+                ..Default::default()
+            }),
+            &ids[1..2]
+        );
+
+        let entries = history.entries(&Query::default()).unwrap();
+        assert_eq!(entries[0].play, plays[0]);
+        assert_eq!(
+            entries[0].submissions[1],
+            Submission {
+                service: "ListenBrainz".to_owned(),
+                status: Status::Pending,
+                attempts: 1,
+                error: Some("Network is unreachable".to_owned()),
+                corrected: None,
+            }
+        );
+
+        let entries = history
+            .entries(&Query {
+                without_submissions: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(entries[0].play, plays[0]);
+        assert!(entries[0].submissions.is_empty());
+
+        let corrected = Track::new("Aphex Twin", "Tha", None);
+        history
+            .set_correction(ids[1], "lastfm:key:alice", &corrected)
+            .unwrap();
+        let entries = history.entries(&Query::default()).unwrap();
+        assert_eq!(entries[1].submissions[0].corrected, Some(corrected));
+        assert_eq!(entries[0].submissions[0].corrected, None);
+    }
+
+    #[test]
+    fn test_import_pending() {
+        let mut history = History::open_in_memory().unwrap();
+
+        history
+            .import_pending(&[state::PendingScrobble {
+                service: "lastfm:key:alice".to_owned(),
+                service_name: "Last.fm (alice)".to_owned(),
+                track: Track::new("Daft Punk", "Veridis Quo", Some("Discovery")),
+                timestamp: 1_000_000_000,
+            }])
+            .unwrap();
+
+        let pending = history.pending("lastfm:key:alice").unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].track.album(), Some("Discovery"));
+        assert_eq!(pending[0].timestamp, 1_000_000_000);
+
+        let decision: Decision = history
+            .connection
+            .query_row("SELECT decision FROM plays", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(decision, Decision::Scrobbled);
+
+        let entries = history.entries(&Query::default()).unwrap();
+        assert_eq!(entries[0].submissions[0].service, "Last.fm (alice)");
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exporting scrobbles from the history, for the `export` command.
+
+use std::io::Write;
+
+use anyhow::Result;
+
+use chrono::DateTime;
+
+use serde_json::{Value, json};
+
+use crate::cli::ExportFormat;
+use crate::output::write_csv_row;
+use crate::player;
+
+use super::Play;
+
+/// The columns of a Last.fm scrobble export, as written by common export tools.
+const CSV_HEADER: &[&str] = &[
+    "uts",
+    "utc_time",
+    "artist",
+    "artist_mbid",
+    "album",
+    "album_mbid",
+    "track",
+    "track_mbid",
+];
+
+fn write_csv(plays: &[Play], out: &mut impl Write) -> Result<()> {
+    write_csv_row(out, CSV_HEADER)?;
+
+    for play in plays {
+        let utc_time = DateTime::from_timestamp(play.started_at as i64, 0)
+            .unwrap_or_default()
+            .format("%d %b %Y, %H:%M")
+            .to_string();
+
+        write_csv_row(
+            out,
+            &[
+                &play.started_at.to_string(),
+                &utc_time,
+                play.track.artist(),
+                "",
+                play.track.album().unwrap_or(""),
+                "",
+                play.track.title(),
+                "",
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn to_listen(play: &Play) -> Value {
+    let mut additional_info = json!({
+        "submission_client": "rescrobbled",
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+        "media_player": player::short_name(&play.player),
+    });
+    if let Some(length) = play.length {
+        additional_info["duration_ms"] = json!(length.as_millis() as u64);
+    }
+
+    json!({
+        "listened_at": play.started_at,
+        "track_metadata": {
+            "artist_name": play.track.artist(),
+            "track_name": play.track.title(),
+            "release_name": play.track.album(),
+            "additional_info": additional_info,
+        },
+    })
+}
+
+/// Make a field safe to write to a `.scrobbler.log` file, which is tab-separated.
+fn log_field(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+fn write_scrobbler_log(plays: &[Play], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "#AUDIOSCROBBLER/1.1")?;
+    writeln!(out, "#TZ/UTC")?;
+    writeln!(out, "#CLIENT/rescrobbled {}", env!("CARGO_PKG_VERSION"))?;
+
+    for play in plays {
+        let length = play.length.unwrap_or(play.play_time);
+
+        writeln!(
+            out,
+            "{}\t{}\t{}\t\t{}\tL\t{}\t",
+            log_field(play.track.artist()),
+            log_field(play.track.album().unwrap_or("")),
+            log_field(play.track.title()),
+            length.as_secs(),
+            play.started_at
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the plays in the given format.
+pub fn write(plays: &[Play], format: ExportFormat, out: &mut impl Write) -> Result<()> {
+    match format {
+        ExportFormat::Csv => write_csv(plays, out),
+        ExportFormat::ListenbrainzJson => {
+            let listens: Vec<_> = plays.iter().map(to_listen).collect();
+            serde_json::to_writer_pretty(&mut *out, &listens)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        ExportFormat::ScrobblerLog => write_scrobbler_log(plays, out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::{Decision, import};
+    use super::*;
+    use crate::cli::ImportFormat;
+    use crate::track::Track;
+
+    fn plays() -> Vec<Play> {
+        vec![
+            Play {
+                track: Track::new("Crosby, Stills & Nash", "Helplessly Hoping", Some("CSN")),
+                player: "org.mpris.MediaPlayer2.spotify".to_owned(),
+                started_at: 1_790_812_800,
+                play_time: Duration::from_secs(160),
+                length: Some(Duration::from_secs(161)),
+                decision: Decision::Scrobbled,
+            },
+            Play {
+                track: Track::new("Khruangbin", "Maria\tTambién", None),
+                player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+                started_at: 1_790_813_100,
+                play_time: Duration::from_secs(200),
+                length: None,
+                decision: Decision::Scrobbled,
+            },
+        ]
+    }
+
+    fn write_string(format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        write(&plays(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        let output = write_string(ExportFormat::Csv);
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert_eq!(
+            lines[1],
+            "1790812800,\"01 Oct 2026, 00:00\",\"Crosby, Stills & Nash\",,CSN,,Helplessly Hoping,"
+        );
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_listenbrainz() {
+        let output = write_string(ExportFormat::ListenbrainzJson);
+
+        let listens: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(listens[0]["track_metadata"]["release_name"], "CSN");
+        assert_eq!(
+            listens[0]["track_metadata"]["additional_info"]["duration_ms"],
+            161_000
+        );
+        assert_eq!(
+            listens[1]["track_metadata"]["additional_info"]["media_player"],
+            "mpv"
+        );
+
+        // Exported listens can be imported again
+        let imported = import::parse(&output, None).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].track, plays()[0].track);
+        assert_eq!(imported[1].started_at, 1_790_813_100);
+    }
+
+    #[test]
+    fn test_scrobbler_log() {
+        let output = write_string(ExportFormat::ScrobblerLog);
+
+        assert!(output.starts_with("#AUDIOSCROBBLER/1.1\n#TZ/UTC\n"));
+
+        let imported = import::parse(&output, Some(ImportFormat::ScrobblerLog)).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].track, plays()[0].track);
+        assert_eq!(imported[0].started_at, 1_790_812_800);
+        assert_eq!(imported[1].track.title(), "Maria También");
+        assert_eq!(imported[1].length, Some(Duration::from_secs(200)));
+    }
+}
@@ -0,0 +1,568 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Importing scrobbles from `.scrobbler.log` files and ListenBrainz exports, for the `import` command.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use log::{error, warn};
+
+use serde::Deserialize;
+
+use crate::cli::ImportFormat;
+use crate::datetime::from_timestamp;
+use crate::service::Service;
+use crate::service::retry::{self, ErrorKind};
+use crate::state::unix_timestamp;
+use crate::track::Track;
+
+use super::{Decision, History, Play, Status};
+
+/// The player recorded for imported plays.
+const IMPORT_PLAYER: &str = "import";
+
+/// A scrobble is a duplicate if the same track was scrobbled this close to it.
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+const SCROBBLER_LOG_HEADER: &str = "#AUDIOSCROBBLER/";
+
+#[derive(Deserialize)]
+struct Listen {
+    listened_at: i64,
+    track_metadata: ListenMetadata,
+}
+
+#[derive(Deserialize)]
+struct ListenMetadata {
+    artist_name: String,
+    track_name: String,
+    release_name: Option<String>,
+    #[serde(default)]
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Deserialize, Default)]
+struct AdditionalInfo {
+    duration_ms: Option<u64>,
+    duration: Option<u64>,
+}
+
+fn imported_play(track: Track, started_at: u64, length: Option<Duration>) -> Play {
+    Play {
+        track,
+        player: IMPORT_PLAYER.to_owned(),
+        started_at,
+        play_time: length.unwrap_or_default(),
+        length,
+        decision: Decision::Scrobbled,
+    }
+}
+
+/// Detect the format of a file from its contents.
+fn detect_format(contents: &str) -> Result<ImportFormat> {
+    let start = contents.trim_start();
+
+    if start.starts_with(SCROBBLER_LOG_HEADER) {
+        Ok(ImportFormat::ScrobblerLog)
+    } else if start.starts_with(['[', '{']) {
+        Ok(ImportFormat::ListenbrainzJson)
+    } else {
+        bail!("Unknown file format, use --format to specify it")
+    }
+}
+
+/// Parse a `.scrobbler.log` file, as written by Rockbox and other portable players.
+/// Only tracks that were listened to (rating `L`) are returned.
+///
+/// See <https://github.com/Rockbox/rockbox/blob/master/apps/plugins/lastfm_scrobbler.c>.
+fn parse_scrobbler_log(contents: &str) -> Result<Vec<Play>> {
+    let mut plays = Vec::new();
+
+    // Without a time zone, timestamps are in local time
+    let mut utc = false;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+
+        if let Some(header) = line.strip_prefix('#') {
+            if let Some(tz) = header.strip_prefix("TZ/") {
+                utc = tz.trim() == "UTC";
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [artist, album, title, _number, length, rating, timestamp, ..] = fields[..] else {
+            bail!(
+                "Line {line_number}: expected at least 7 tab-separated fields, found {}",
+                fields.len()
+            );
+        };
+
+        if rating != "L" {
+            continue;
+        }
+
+        let timestamp: i64 = timestamp
+            .parse()
+            .with_context(|| format!("Line {line_number}: invalid timestamp `{timestamp}`"))?;
+
+        let timestamp = if utc {
+            timestamp
+        } else {
+            local_timestamp(timestamp)
+                .with_context(|| format!("Line {line_number}: invalid timestamp `{timestamp}`"))?
+        };
+
+        let length = length
+            .parse()
+            .ok()
+            .filter(|&length| length > 0)
+            .map(Duration::from_secs);
+
+        plays.push(imported_play(
+            Track::new(artist, title, Some(album)),
+            timestamp.max(0) as u64,
+            length,
+        ));
+    }
+
+    Ok(plays)
+}
+
+/// Convert a timestamp in local time (i.e. seconds since the UNIX epoch in the local
+/// time zone instead of UTC) to a UNIX timestamp.
+fn local_timestamp(timestamp: i64) -> Result<i64> {
+    let local = DateTime::from_timestamp(timestamp, 0)
+        .ok_or_else(|| anyhow!("Timestamp out of range"))?
+        .naive_utc();
+
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| anyhow!("{local} does not exist in the local time zone"))
+}
+
+/// Parse a ListenBrainz export, either a JSON array of listens or one listen per line.
+fn parse_listenbrainz(contents: &str) -> Result<Vec<Play>> {
+    let listens: Vec<Listen> = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(contents).context("Invalid ListenBrainz export")?
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Line {}: invalid ListenBrainz listen", index + 1))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    let plays = listens
+        .into_iter()
+        .map(|listen| {
+            let metadata = listen.track_metadata;
+            let info = metadata.additional_info;
+
+            let length = info
+                .duration_ms
+                .map(Duration::from_millis)
+                .or(info.duration.map(Duration::from_secs))
+                .filter(|length| !length.is_zero());
+
+            imported_play(
+                Track::new(
+                    &metadata.artist_name,
+                    &metadata.track_name,
+                    metadata.release_name.as_deref(),
+                ),
+                listen.listened_at.max(0) as u64,
+                length,
+            )
+        })
+        .collect();
+
+    Ok(plays)
+}
+
+/// Parse a file in the given format, or detect the format if it is not given.
+pub fn parse(contents: &str, format: Option<ImportFormat>) -> Result<Vec<Play>> {
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(contents)?,
+    };
+
+    match format {
+        ImportFormat::ScrobblerLog => parse_scrobbler_log(contents),
+        ImportFormat::ListenbrainzJson => parse_listenbrainz(contents),
+    }
+}
+
+/// Remove scrobbles that occur more than once, or that are already in the history.
+fn deduplicate(history: &History, plays: Vec<Play>) -> Result<Vec<Play>> {
+    let mut seen = HashSet::new();
+    let mut unique = Vec::new();
+
+    for play in plays {
+        let key = (
+            play.track.artist().to_lowercase(),
+            play.track.title().to_lowercase(),
+            play.started_at,
+        );
+
+        if seen.insert(key) && !history.contains(&play.track, play.started_at, DUPLICATE_WINDOW)? {
+            unique.push(play);
+        }
+    }
+
+    Ok(unique)
+}
+
+/// Submit imported plays to a service in batches, and record the outcome in the history.
+/// If a batch fails, it and the remaining plays are left pending, to be retried later.
+fn submit(service: &Service, history: &History, plays: &[(i64, Play)]) -> Result<()> {
+    let (service_id, name) = (service.id(), service.to_string());
+
+    let now = unix_timestamp(&SystemTime::now());
+    let (plays, too_old): (Vec<_>, Vec<_>) = plays.iter().partition(|(_, play)| {
+        service
+            .max_age()
+            .is_none_or(|max_age| now.saturating_sub(play.started_at) < max_age.as_secs())
+    });
+
+    let mut submitted = 0;
+    let mut rejected = 0;
+
+    if !too_old.is_empty() {
+        warn!(
+            "{} scrobble(s) are too old to submit to {name}, recording them as rejected",
+            too_old.len()
+        );
+
+        let err = anyhow!("Too old to submit to {name}");
+        for (id, play) in &too_old {
+            history.add_submission(
+                *id,
+                &service_id,
+                &name,
+                play.started_at,
+                Status::Rejected,
+                Some(&err),
+            )?;
+        }
+        rejected += too_old.len();
+    }
+    let mut pending = 0;
+
+    for (index, batch) in plays.chunks(service.batch_size()).enumerate() {
+        let scrobbles: Vec<_> = batch
+            .iter()
+            .map(|(_, play)| (&play.track, play.started_at))
+            .collect();
+
+        let results = match service.submit_batch(&scrobbles) {
+            Ok(results) => results,
+            Err(err) => {
+                error!("{:#}", err);
+
+                if let (ErrorKind::Rejected, _) = retry::classify(&err) {
+                    for (id, play) in batch {
+                        history.add_submission(
+                            *id,
+                            &service_id,
+                            &name,
+                            play.started_at,
+                            Status::Rejected,
+                            Some(&err),
+                        )?;
+                    }
+                    rejected += batch.len();
+                    continue;
+                }
+
+                let remaining = &plays[index * service.batch_size()..];
+                for (id, play) in remaining {
+                    history.add_submission(
+                        *id,
+                        &service_id,
+                        &name,
+                        play.started_at,
+                        Status::Pending,
+                        Some(&err),
+                    )?;
+                }
+
+                println!(
+                    "Submitted {submitted} scrobble(s) to {name}, \
+                    {} scrobble(s) failed and will be retried by rescrobbled",
+                    remaining.len() + pending
+                );
+                return Ok(());
+            }
+        };
+
+        for ((id, play), result) in batch.iter().zip(results) {
+            match result {
+                Ok(accepted) => {
+                    history.add_submission(
+                        *id,
+                        &service_id,
+                        &name,
+                        play.started_at,
+                        Status::Submitted,
+                        None,
+                    )?;
+
+                    if let Some(ref corrected) = accepted.corrected {
+                        history.set_correction(*id, &service_id, corrected)?;
+                    }
+
+                    submitted += 1;
+                }
+                // The service ignored this scrobble
+                Err(err) => {
+                    warn!("{:#}", err);
+
+                    let status = match retry::classify(&err) {
+                        (ErrorKind::Rejected, _) => {
+                            rejected += 1;
+                            Status::Rejected
+                        }
+                        _ => {
+                            pending += 1;
+                            Status::Pending
+                        }
+                    };
+
+                    history.add_submission(
+                        *id,
+                        &service_id,
+                        &name,
+                        play.started_at,
+                        status,
+                        Some(&err),
+                    )?;
+                }
+            }
+        }
+    }
+
+    let mut summary = format!("Submitted {submitted} scrobble(s) to {name}");
+    if rejected > 0 {
+        summary.push_str(&format!(", {name} rejected {rejected} scrobble(s)"));
+    }
+    if pending > 0 {
+        summary.push_str(&format!(
+            ", {pending} scrobble(s) failed and will be retried by rescrobbled"
+        ));
+    }
+    println!("{summary}");
+
+    Ok(())
+}
+
+/// Add plays that are not in the history yet to the history, and submit them to all services.
+/// With `dry_run`, only print the plays that would be imported.
+pub fn import(
+    history: &History,
+    services: &[Service],
+    plays: Vec<Play>,
+    dry_run: bool,
+) -> Result<()> {
+    let total = plays.len();
+
+    let plays = deduplicate(history, plays)?;
+
+    println!(
+        "Found {total} scrobble(s), {} of which are already in the history",
+        total - plays.len()
+    );
+
+    if dry_run {
+        for play in &plays {
+            println!(
+                "{}  {} - {}",
+                from_timestamp(play.started_at).format("%Y-%m-%d %H:%M"),
+                play.track.artist(),
+                play.track.title()
+            );
+        }
+        return Ok(());
+    }
+
+    if plays.is_empty() {
+        return Ok(());
+    }
+
+    // Otherwise the plays would be in the history without being submitted,
+    // and importing again would skip them
+    if services.is_empty() {
+        bail!("No scrobbling services are available to submit the scrobbles to");
+    }
+
+    let plays = plays
+        .into_iter()
+        .map(|play| Ok((history.add_play(&play)?, play)))
+        .collect::<Result<Vec<_>>>()?;
+
+    println!("Added {} scrobble(s) to the history", plays.len());
+
+    for service in services {
+        submit(service, history, &plays)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::service::{scrobble_response, serve};
+
+    #[test]
+    fn test_scrobbler_log() {
+        let log = "#AUDIOSCROBBLER/1.1\n\
+            #TZ/UTC\n\
+            #CLIENT/Rockbox sansaclipplus $Revision$\n\
+            Boards of Canada\tGeogaddi\tMusic Is Math\t5\t321\tL\t1790812800\t\n\
+            Boards of Canada\tGeogaddi\tBeware the Friendly Stranger\t6\t37\tS\t1790813121\t\n\
+            Boards of Canada\t\tDawn Chorus\t7\t0\tL\t1790813200\n";
+
+        assert_eq!(detect_format(log).unwrap(), ImportFormat::ScrobblerLog);
+
+        let plays = parse(log, None).unwrap();
+        assert_eq!(
+            plays,
+            vec![
+                imported_play(
+                    Track::new("Boards of Canada", "Music Is Math", Some("Geogaddi")),
+                    1_790_812_800,
+                    Some(Duration::from_secs(321))
+                ),
+                imported_play(
+                    Track::new("Boards of Canada", "Dawn Chorus", None),
+                    1_790_813_200,
+                    None
+                ),
+            ]
+        );
+
+        // Timestamps are in local time if the time zone is unknown
+        let local = parse(&log.replace("#TZ/UTC", "#TZ/UNKNOWN"), None).unwrap();
+        assert_eq!(
+            Some(local[0].started_at as i64),
+            Local
+                .with_ymd_and_hms(2026, 10, 1, 0, 0, 0)
+                .single()
+                .map(|time| time.timestamp())
+        );
+
+        assert!(parse("#AUDIOSCROBBLER/1.1\nArtist\tAlbum\tTitle\n", None).is_err());
+    }
+
+    #[test]
+    fn test_listenbrainz() {
+        let listen = r#"{
+            "listened_at": 1790812800,
+            "track_metadata": {
+                "artist_name": "Bonobo",
+                "track_name": "Kerala",
+                "release_name": "Migration",
+                "additional_info": {"duration_ms": 244000, "listening_from": "web"}
+            }
+        }"#;
+        let expected = imported_play(
+            Track::new("Bonobo", "Kerala", Some("Migration")),
+            1_790_812_800,
+            Some(Duration::from_secs(244)),
+        );
+
+        let array = format!("[{listen}]");
+        assert_eq!(parse(&array, None).unwrap(), vec![expected.clone()]);
+
+        let lines = format!(
+            "{}\n{}\n",
+            listen.replace('\n', ""),
+            r#"{"listened_at": 1790813100, "track_metadata": {"artist_name": "Bonobo", "track_name": "Cirrus"}}"#
+        );
+        let plays = parse(&lines, Some(ImportFormat::ListenbrainzJson)).unwrap();
+        assert_eq!(plays[0], expected);
+        assert_eq!(plays[1].track, Track::new("Bonobo", "Cirrus", None));
+        assert_eq!(plays[1].length, None);
+    }
+
+    #[test]
+    fn test_import() {
+        let history = History::open_in_memory().unwrap();
+
+        // Recent enough to be submitted to Last.fm
+        let start = unix_timestamp(&SystemTime::now()) - 24 * 60 * 60;
+        let play = |title: &str, started_at| {
+            imported_play(Track::new("Bonobo", title, None), start + started_at, None)
+        };
+
+        history
+            .add_play(&Play {
+                player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+                ..play("Kerala", 1000)
+            })
+            .unwrap();
+
+        let plays = vec![
+            // Recorded while running, with a slightly different start time
+            play("KERALA", 1100),
+            play("Cirrus", 2000),
+            play("Cirrus", 2000),
+            play("Cirrus", 5000),
+            // Too old for Last.fm
+            imported_play(Track::new("Bonobo", "Flashlight", None), 1000, None),
+        ];
+
+        import(&history, &[], plays.clone(), true).unwrap();
+        assert_eq!(history.entries(&Default::default()).unwrap().len(), 1);
+
+        // Nothing is recorded if the scrobbles cannot be submitted
+        assert!(import(&history, &[], plays.clone(), false).is_err());
+        assert_eq!(history.entries(&Default::default()).unwrap().len(), 1);
+
+        let (url, server) = serve(&[(200, &scrobble_response(&[None, None]))]);
+        let services = [Service::mock_lastfm(&url)];
+
+        import(&history, &services, plays.clone(), false).unwrap();
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        let entries = history.entries(&Default::default()).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].play.started_at, 1000);
+        assert_eq!(entries[0].submissions[0].status, Status::Rejected);
+        assert_eq!(entries[2].play, play("Cirrus", 2000));
+        assert_eq!(entries[2].submissions[0].status, Status::Submitted);
+
+        // Importing again does not add anything
+        import(&history, &services, plays, false).unwrap();
+        assert_eq!(history.entries(&Default::default()).unwrap().len(), 4);
+    }
+}
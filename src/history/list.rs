@@ -0,0 +1,301 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Output of the `history` command.
+
+use std::io::Write;
+
+use anyhow::Result;
+
+use serde_json::{Value, json};
+
+use crate::cli::HistoryFormat;
+use crate::datetime::{format_duration, from_timestamp};
+use crate::output::{Table, write_csv_row};
+use crate::player;
+
+use super::{Decision, Entry, Status, Submission};
+
+const CSV_HEADER: &[&str] = &[
+    "time",
+    "artist",
+    "title",
+    "album",
+    "player",
+    "play_time",
+    "length",
+    "decision",
+    "submissions",
+];
+
+/// Describe the outcome of a submission, e.g. `Last.fm: failed after 2 attempts (...)`.
+fn describe(submission: &Submission) -> String {
+    match submission.status {
+        Status::Submitted => match submission.corrected {
+            Some(ref track) => format!(
+                "{}: submitted as {} - {}",
+                submission.service,
+                track.artist(),
+                track.title()
+            ),
+            None => format!("{}: submitted", submission.service),
+        },
+        Status::Pending if submission.attempts == 0 => {
+            format!("{}: waiting to submit", submission.service)
+        }
+        Status::Rejected => match submission.error {
+            Some(ref error) => format!("{}: rejected ({error})", submission.service),
+            None => format!("{}: rejected", submission.service),
+        },
+        Status::Pending => {
+            let mut description = format!(
+                "{}: failed after {} attempt(s)",
+                submission.service, submission.attempts
+            );
+            if let Some(ref error) = submission.error {
+                description.push_str(&format!(" ({error})"));
+            }
+            description
+        }
+    }
+}
+
+/// Describe what happened to a play.
+fn status(entry: &Entry) -> String {
+    match entry.play.decision {
+        Decision::Scrobbled if !entry.submissions.is_empty() => entry
+            .submissions
+            .iter()
+            .map(describe)
+            .collect::<Vec<_>>()
+            .join(", "),
+        Decision::Scrobbled => "scrobbled (no services)".to_owned(),
+        Decision::Ignored => "ignored by filter script".to_owned(),
+        Decision::Skipped => "skipped".to_owned(),
+    }
+}
+
+fn write_table(entries: &[Entry], out: &mut impl Write) -> Result<()> {
+    if entries.is_empty() {
+        writeln!(out, "No plays found")?;
+        return Ok(());
+    }
+
+    let mut table = Table::new(&[
+        "Time", "Artist", "Title", "Album", "Player", "Played", "Status",
+    ]);
+
+    for entry in entries {
+        let play = &entry.play;
+
+        let played = match play.length {
+            Some(length) => format!(
+                "{}/{}",
+                format_duration(play.play_time),
+                format_duration(length)
+            ),
+            None => format_duration(play.play_time),
+        };
+
+        table.push(vec![
+            from_timestamp(play.started_at)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            play.track.artist().to_owned(),
+            play.track.title().to_owned(),
+            play.track.album().unwrap_or("").to_owned(),
+            player::short_name(&play.player).to_owned(),
+            played,
+            status(entry),
+        ]);
+    }
+
+    table.write(out)?;
+
+    Ok(())
+}
+
+fn to_json(entry: &Entry) -> Value {
+    let play = &entry.play;
+
+    json!({
+        "id": entry.id,
+        "time": from_timestamp(play.started_at).to_rfc3339(),
+        "timestamp": play.started_at,
+        "artist": play.track.artist(),
+        "title": play.track.title(),
+        "album": play.track.album(),
+        "player": play.player,
+        "play_time": play.play_time.as_secs(),
+        "length": play.length.map(|length| length.as_secs()),
+        "decision": play.decision.as_str(),
+        "submissions": entry.submissions.iter().map(|submission| json!({
+            "service": submission.service,
+            "status": submission.status.as_str(),
+            "attempts": submission.attempts,
+            "error": submission.error,
+            "corrected": submission.corrected.as_ref().map(|track| json!({
+                "artist": track.artist(),
+                "title": track.title(),
+                "album": track.album(),
+            })),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn write_csv(entries: &[Entry], out: &mut impl Write) -> Result<()> {
+    write_csv_row(out, CSV_HEADER)?;
+
+    for entry in entries {
+        let play = &entry.play;
+
+        write_csv_row(
+            out,
+            &[
+                from_timestamp(play.started_at).to_rfc3339(),
+                play.track.artist().to_owned(),
+                play.track.title().to_owned(),
+                play.track.album().unwrap_or("").to_owned(),
+                play.player.clone(),
+                play.play_time.as_secs().to_string(),
+                play.length
+                    .map(|length| length.as_secs().to_string())
+                    .unwrap_or_default(),
+                play.decision.as_str().to_owned(),
+                entry
+                    .submissions
+                    .iter()
+                    .map(describe)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the entries in the given format.
+pub fn write(entries: &[Entry], format: HistoryFormat, out: &mut impl Write) -> Result<()> {
+    match format {
+        HistoryFormat::Table => write_table(entries, out),
+        HistoryFormat::Json => {
+            let entries: Vec<_> = entries.iter().map(to_json).collect();
+            serde_json::to_writer_pretty(&mut *out, &entries)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        HistoryFormat::Csv => write_csv(entries, out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::Play;
+    use super::*;
+    use crate::track::Track;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                id: 1,
+                play: Play {
+                    track: Track::new("Radiohead", "Reckoner", Some("In Rainbows")),
+                    player: "org.mpris.MediaPlayer2.spotify".to_owned(),
+                    started_at: 1_790_812_800,
+                    play_time: Duration::from_secs(150),
+                    length: Some(Duration::from_secs(290)),
+                    decision: Decision::Scrobbled,
+                },
+                submissions: vec![
+                    Submission {
+                        service: "Last.fm".to_owned(),
+                        status: Status::Pending,
+                        attempts: 2,
+                        error: Some("Network is unreachable".to_owned()),
+                        corrected: None,
+                    },
+                    Submission {
+                        service: "ListenBrainz".to_owned(),
+                        status: Status::Submitted,
+                        attempts: 1,
+                        error: None,
+                        corrected: Some(Track::new("Radiohead", "Reckoner", None)),
+                    },
+                ],
+            },
+            Entry {
+                id: 2,
+                play: Play {
+                    track: Track::new("Radiohead", "Nude", None),
+                    player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+                    started_at: 1_790_813_100,
+                    play_time: Duration::from_secs(20),
+                    length: None,
+                    decision: Decision::Skipped,
+                },
+                submissions: Vec::new(),
+            },
+        ]
+    }
+
+    fn write_string(format: HistoryFormat) -> String {
+        let mut out = Vec::new();
+        write(&entries(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_table() {
+        let output = write_string(HistoryFormat::Table);
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("Time "));
+        assert!(lines[1].contains("Reckoner  In Rainbows  spotify  2:30/4:50  Last.fm: failed after 2 attempt(s) (Network is unreachable), ListenBrainz: submitted as Radiohead - Reckoner"));
+        assert!(lines[2].contains("Nude"));
+        assert!(lines[2].ends_with("mpv      0:20       skipped"));
+    }
+
+    #[test]
+    fn test_json() {
+        let output: Value = serde_json::from_str(&write_string(HistoryFormat::Json)).unwrap();
+
+        assert_eq!(output[0]["timestamp"], 1_790_812_800);
+        assert_eq!(output[0]["submissions"][0]["status"], "pending");
+        assert_eq!(
+            output[0]["submissions"][1]["corrected"]["title"],
+            "Reckoner"
+        );
+        assert_eq!(output[1]["album"], Value::Null);
+        assert_eq!(output[1]["decision"], "skipped");
+    }
+
+    #[test]
+    fn test_csv() {
+        let output = write_string(HistoryFormat::Csv);
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert!(lines[1].ends_with(
+            ",Radiohead,Reckoner,In Rainbows,org.mpris.MediaPlayer2.spotify,150,290,scrobbled,\
+            Last.fm: failed after 2 attempt(s) (Network is unreachable); \
+            ListenBrainz: submitted as Radiohead - Reckoner"
+        ));
+        assert!(lines[2].ends_with(",Radiohead,Nude,,org.mpris.MediaPlayer2.mpv,20,,skipped,"));
+    }
+}
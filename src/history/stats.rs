@@ -0,0 +1,562 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Listening statistics computed from the history, for the `stats` command.
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+
+use serde_json::{Value, json};
+
+use crate::cli::{StatsFormat, StatsPeriod};
+use crate::datetime::{format_duration, from_timestamp};
+use crate::output::Table;
+use crate::player;
+
+use super::Entry;
+
+/// The number of scrobbles and the listening time for an artist, album, track, period or player.
+#[derive(Debug, PartialEq)]
+pub struct Count<K> {
+    pub key: K,
+    pub scrobbles: usize,
+    pub time: Duration,
+}
+
+/// A run of consecutive days with at least one scrobble.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Streak {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl Streak {
+    pub fn days(&self) -> i64 {
+        (self.end - self.start).num_days() + 1
+    }
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub since: Option<DateTime<Local>>,
+    pub until: DateTime<Local>,
+    pub scrobbles: usize,
+    pub listening_time: Duration,
+    pub artist_count: usize,
+    pub track_count: usize,
+    pub artists: Vec<Count<String>>,
+    /// Albums as (artist, album).
+    pub albums: Vec<Count<(String, String)>>,
+    /// Tracks as (artist, title).
+    pub tracks: Vec<Count<(String, String)>>,
+    pub period: StatsPeriod,
+    /// Days (`2026-10-18`) or ISO weeks (`2026-W42`), in chronological order.
+    pub periods: Vec<Count<String>>,
+    pub players: Vec<Count<String>>,
+    pub longest_streak: Option<Streak>,
+    /// The streak that includes the last day of the period, or the day before.
+    pub current_streak: Option<Streak>,
+}
+
+/// Count scrobbles and listening time per key.
+struct Counter<K> {
+    counts: HashMap<K, (usize, Duration)>,
+}
+
+impl<K: Eq + Hash + Ord> Counter<K> {
+    fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, key: K, time: Duration) {
+        let (scrobbles, total) = self.counts.entry(key).or_default();
+        *scrobbles += 1;
+        *total += time;
+    }
+
+    fn into_counts(self) -> Vec<Count<K>> {
+        self.counts
+            .into_iter()
+            .map(|(key, (scrobbles, time))| Count {
+                key,
+                scrobbles,
+                time,
+            })
+            .collect()
+    }
+
+    /// The counts with the most scrobbles first, limited to `limit` entries.
+    fn top(self, limit: usize) -> Vec<Count<K>> {
+        let mut counts = self.into_counts();
+        counts.sort_by(|a, b| {
+            b.scrobbles
+                .cmp(&a.scrobbles)
+                .then(b.time.cmp(&a.time))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        counts.truncate(limit);
+        counts
+    }
+
+    /// The counts ordered by key.
+    fn sorted(self) -> Vec<Count<K>> {
+        let mut counts = self.into_counts();
+        counts.sort_by(|a, b| a.key.cmp(&b.key));
+        counts
+    }
+}
+
+/// Find the longest streak and the streak that ends on `last_day` or the day before.
+fn streaks(days: &BTreeSet<NaiveDate>, last_day: NaiveDate) -> (Option<Streak>, Option<Streak>) {
+    let mut streaks: Vec<Streak> = Vec::new();
+
+    for &day in days {
+        match streaks.last_mut() {
+            Some(streak) if streak.end.succ_opt() == Some(day) => streak.end = day,
+            _ => streaks.push(Streak {
+                start: day,
+                end: day,
+            }),
+        }
+    }
+
+    let current = streaks
+        .iter()
+        .find(|streak| streak.end == last_day || streak.end.succ_opt() == Some(last_day))
+        .copied();
+
+    // If there are multiple longest streaks, this is the most recent one
+    let longest = streaks.into_iter().max_by_key(Streak::days);
+
+    (longest, current)
+}
+
+/// Compute statistics from scrobbled plays.
+///
+/// The listening time of a play is the length of the track if it is known,
+/// otherwise how long it had played when it was scrobbled.
+pub fn compute(
+    entries: &[Entry],
+    since: Option<DateTime<Local>>,
+    until: DateTime<Local>,
+    period: StatsPeriod,
+    limit: usize,
+) -> Stats {
+    let mut artists = Counter::new();
+    let mut albums = Counter::new();
+    let mut tracks = Counter::new();
+    let mut periods = Counter::new();
+    let mut players = Counter::new();
+    let mut days = BTreeSet::new();
+
+    let mut listening_time = Duration::ZERO;
+
+    for entry in entries {
+        let play = &entry.play;
+        let track = &play.track;
+        let time = play.length.unwrap_or(play.play_time);
+
+        let day = from_timestamp(play.started_at).date_naive();
+
+        listening_time += time;
+
+        artists.add(track.artist().to_owned(), time);
+        if let Some(album) = track.album() {
+            albums.add((track.artist().to_owned(), album.to_owned()), time);
+        }
+        tracks.add((track.artist().to_owned(), track.title().to_owned()), time);
+
+        periods.add(
+            match period {
+                StatsPeriod::Day => day.format("%Y-%m-%d").to_string(),
+                StatsPeriod::Week => {
+                    let week = day.iso_week();
+                    format!("{}-W{:02}", week.year(), week.week())
+                }
+            },
+            time,
+        );
+
+        players.add(player::short_name(&play.player).to_owned(), time);
+
+        days.insert(day);
+    }
+
+    let (longest_streak, current_streak) = streaks(&days, until.date_naive());
+
+    Stats {
+        since,
+        until,
+        scrobbles: entries.len(),
+        listening_time,
+        artist_count: artists.counts.len(),
+        track_count: tracks.counts.len(),
+        artists: artists.top(limit),
+        albums: albums.top(limit),
+        tracks: tracks.top(limit),
+        period,
+        periods: periods.sorted(),
+        players: players.top(usize::MAX),
+        longest_streak,
+        current_streak,
+    }
+}
+
+fn describe_streak(streak: &Option<Streak>) -> String {
+    match streak {
+        Some(streak) if streak.days() == 1 => format!("1 day ({})", streak.start),
+        Some(streak) => format!(
+            "{} days ({} to {})",
+            streak.days(),
+            streak.start,
+            streak.end
+        ),
+        None => "none".to_owned(),
+    }
+}
+
+/// Write a table of counts, with the given columns before the scrobbles and time.
+fn write_counts<K>(
+    out: &mut impl Write,
+    title: &str,
+    header: &[&str],
+    counts: &[Count<K>],
+    ranked: bool,
+    key: impl Fn(&K) -> Vec<String>,
+) -> Result<()> {
+    writeln!(out, "\n{title}\n")?;
+
+    if counts.is_empty() {
+        writeln!(out, "(none)")?;
+        return Ok(());
+    }
+
+    let mut columns = Vec::new();
+    if ranked {
+        columns.push("#");
+    }
+    columns.extend_from_slice(header);
+    columns.extend_from_slice(&["Scrobbles", "Time"]);
+
+    let offset = columns.len() - 2;
+
+    let mut table = Table::new(&columns)
+        .right_align(offset)
+        .right_align(offset + 1);
+    if ranked {
+        table = table.right_align(0);
+    }
+
+    for (index, count) in counts.iter().enumerate() {
+        let mut row = Vec::new();
+        if ranked {
+            row.push((index + 1).to_string());
+        }
+        row.extend(key(&count.key));
+        row.push(count.scrobbles.to_string());
+        row.push(format_duration(count.time));
+
+        table.push(row);
+    }
+
+    table.write(out)?;
+
+    Ok(())
+}
+
+fn write_text(stats: &Stats, out: &mut impl Write) -> Result<()> {
+    let format = "%Y-%m-%d %H:%M";
+    match stats.since {
+        Some(since) => writeln!(
+            out,
+            "Listening statistics from {} to {}",
+            since.format(format),
+            stats.until.format(format)
+        )?,
+        None => writeln!(
+            out,
+            "Listening statistics until {}",
+            stats.until.format(format)
+        )?,
+    }
+
+    writeln!(out)?;
+
+    for (name, value) in [
+        ("Scrobbles:", stats.scrobbles.to_string()),
+        ("Listening time:", format_duration(stats.listening_time)),
+        ("Artists:", stats.artist_count.to_string()),
+        ("Tracks:", stats.track_count.to_string()),
+        ("Current streak:", describe_streak(&stats.current_streak)),
+        ("Longest streak:", describe_streak(&stats.longest_streak)),
+    ] {
+        writeln!(out, "{name:<16}{value}")?;
+    }
+
+    write_counts(
+        out,
+        "Top artists",
+        &["Artist"],
+        &stats.artists,
+        true,
+        |artist| vec![artist.clone()],
+    )?;
+    write_counts(
+        out,
+        "Top albums",
+        &["Album", "Artist"],
+        &stats.albums,
+        true,
+        |(artist, album)| vec![album.clone(), artist.clone()],
+    )?;
+    write_counts(
+        out,
+        "Top tracks",
+        &["Title", "Artist"],
+        &stats.tracks,
+        true,
+        |(artist, title)| vec![title.clone(), artist.clone()],
+    )?;
+
+    let (title, column) = match stats.period {
+        StatsPeriod::Day => ("Listening time per day", "Day"),
+        StatsPeriod::Week => ("Listening time per week", "Week"),
+    };
+    write_counts(out, title, &[column], &stats.periods, false, |period| {
+        vec![period.clone()]
+    })?;
+
+    write_counts(
+        out,
+        "Players",
+        &["Player"],
+        &stats.players,
+        false,
+        |player| vec![player.clone()],
+    )?;
+
+    Ok(())
+}
+
+fn count_json<K>(counts: &[Count<K>], key: impl Fn(&K) -> Value) -> Value {
+    counts
+        .iter()
+        .map(|count| {
+            let mut value = key(&count.key);
+            value["scrobbles"] = count.scrobbles.into();
+            value["time"] = count.time.as_secs().into();
+            value
+        })
+        .collect()
+}
+
+fn streak_json(streak: &Option<Streak>) -> Value {
+    match streak {
+        Some(streak) => json!({
+            "start": streak.start.to_string(),
+            "end": streak.end.to_string(),
+            "days": streak.days(),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn to_json(stats: &Stats) -> Value {
+    let period = match stats.period {
+        StatsPeriod::Day => "day",
+        StatsPeriod::Week => "week",
+    };
+
+    json!({
+        "since": stats.since.map(|since| since.to_rfc3339()),
+        "until": stats.until.to_rfc3339(),
+        "scrobbles": stats.scrobbles,
+        "listening_time": stats.listening_time.as_secs(),
+        "artist_count": stats.artist_count,
+        "track_count": stats.track_count,
+        "top_artists": count_json(&stats.artists, |artist| json!({ "artist": artist })),
+        "top_albums": count_json(&stats.albums, |(artist, album)| json!({
+            "artist": artist,
+            "album": album,
+        })),
+        "top_tracks": count_json(&stats.tracks, |(artist, title)| json!({
+            "artist": artist,
+            "title": title,
+        })),
+        "listening_time_per_period": count_json(&stats.periods, |key| json!({ period: key })),
+        "players": count_json(&stats.players, |player| json!({ "player": player })),
+        "current_streak": streak_json(&stats.current_streak),
+        "longest_streak": streak_json(&stats.longest_streak),
+    })
+}
+
+/// Write the statistics in the given format.
+pub fn write(stats: &Stats, format: StatsFormat, out: &mut impl Write) -> Result<()> {
+    match format {
+        StatsFormat::Text => write_text(stats, out),
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &to_json(stats))?;
+            writeln!(out)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::super::{Decision, Play};
+    use super::*;
+    use crate::track::Track;
+
+    fn entry(artist: &str, title: &str, album: Option<&str>, day: u32, hour: u32) -> Entry {
+        let started_at = Local
+            .with_ymd_and_hms(2026, 10, day, hour, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+
+        Entry {
+            id: 0,
+            play: Play {
+                track: Track::new(artist, title, album),
+                player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+                started_at,
+                play_time: Duration::from_secs(60),
+                length: (album.is_some()).then_some(Duration::from_secs(200)),
+                decision: Decision::Scrobbled,
+            },
+            submissions: Vec::new(),
+        }
+    }
+
+    fn stats(period: StatsPeriod) -> Stats {
+        let entries = [
+            entry("Massive Attack", "Teardrop", Some("Mezzanine"), 1, 10),
+            entry("Massive Attack", "Angel", Some("Mezzanine"), 2, 10),
+            entry("Portishead", "Roads", None, 2, 23),
+            entry("Massive Attack", "Teardrop", Some("Mezzanine"), 3, 10),
+            entry("Portishead", "Glory Box", None, 6, 10),
+            entry("Tricky", "Overcome", None, 7, 10),
+        ];
+
+        let until = Local.with_ymd_and_hms(2026, 10, 8, 12, 0, 0).unwrap();
+
+        compute(&entries, None, until, period, 2)
+    }
+
+    #[test]
+    fn test_compute() {
+        let stats = stats(StatsPeriod::Day);
+
+        assert_eq!(stats.scrobbles, 6);
+        assert_eq!(stats.listening_time, Duration::from_secs(3 * 200 + 3 * 60));
+        assert_eq!(stats.artist_count, 3);
+        assert_eq!(stats.track_count, 5);
+
+        assert_eq!(
+            stats.artists,
+            vec![
+                Count {
+                    key: "Massive Attack".to_owned(),
+                    scrobbles: 3,
+                    time: Duration::from_secs(600),
+                },
+                Count {
+                    key: "Portishead".to_owned(),
+                    scrobbles: 2,
+                    time: Duration::from_secs(120),
+                },
+            ]
+        );
+        assert_eq!(stats.albums.len(), 1);
+        assert_eq!(
+            stats.tracks[0].key,
+            ("Massive Attack".to_owned(), "Teardrop".to_owned())
+        );
+
+        let days: Vec<_> = stats
+            .periods
+            .iter()
+            .map(|count| (count.key.as_str(), count.scrobbles))
+            .collect();
+        assert_eq!(
+            days,
+            [
+                ("2026-10-01", 1),
+                ("2026-10-02", 2),
+                ("2026-10-03", 1),
+                ("2026-10-06", 1),
+                ("2026-10-07", 1)
+            ]
+        );
+
+        assert_eq!(stats.players[0].key, "mpv");
+
+        let date = |day| NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+        assert_eq!(
+            stats.longest_streak,
+            Some(Streak {
+                start: date(1),
+                end: date(3),
+            })
+        );
+        assert_eq!(
+            stats.current_streak,
+            Some(Streak {
+                start: date(6),
+                end: date(7),
+            })
+        );
+
+        let stats = super::tests::stats(StatsPeriod::Week);
+        let weeks: Vec<_> = stats
+            .periods
+            .iter()
+            .map(|count| (count.key.as_str(), count.scrobbles))
+            .collect();
+        assert_eq!(weeks, [("2026-W40", 4), ("2026-W41", 2)]);
+    }
+
+    #[test]
+    fn test_write() {
+        let stats = stats(StatsPeriod::Day);
+
+        let mut out = Vec::new();
+        write(&stats, StatsFormat::Text, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("\nScrobbles:      6\n"));
+        assert!(text.contains("\nCurrent streak: 2 days (2026-10-06 to 2026-10-07)\n"));
+        assert!(text.contains("\n1  Massive Attack          3  10:00\n"));
+
+        let mut out = Vec::new();
+        write(&stats, StatsFormat::Json, &mut out).unwrap();
+        let json: Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(json["top_albums"][0]["album"], "Mezzanine");
+        assert_eq!(json["top_albums"][0]["time"], 600);
+        assert_eq!(json["listening_time_per_period"][1]["day"], "2026-10-02");
+        assert_eq!(json["longest_streak"]["days"], 3);
+    }
+}
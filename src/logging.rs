@@ -0,0 +1,229 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicBool, AtomicI8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+struct Logger {
+    /// Whether stderr is connected to the systemd journal.
+    journald: AtomicBool,
+    json: AtomicBool,
+    /// Number of levels to raise (`-v`) or lower (`-q`) the configured log level.
+    verbosity: AtomicI8,
+}
+
+static LOGGER: Logger = Logger {
+    journald: AtomicBool::new(false),
+    json: AtomicBool::new(false),
+    verbosity: AtomicI8::new(0),
+};
+
+/// Determine if stderr is connected to the journal, by comparing it to the
+/// device and inode in `$JOURNAL_STREAM` (see `systemd.exec(5)`).
+fn is_journald() -> bool {
+    let Ok(stream) = env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+
+    let Some((device, inode)) = stream.split_once(':') else {
+        return false;
+    };
+
+    let metadata = io::stderr()
+        .as_fd()
+        .try_clone_to_owned()
+        .map(File::from)
+        .and_then(|file| file.metadata());
+
+    match metadata {
+        Ok(metadata) => device.parse() == Ok(metadata.dev()) && inode.parse() == Ok(metadata.ino()),
+        Err(_) => false,
+    }
+}
+
+/// Get the syslog priority of a log level, used as a `<N>` prefix by the journal.
+fn syslog_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn adjust_level(level: LevelFilter, verbosity: i8) -> LevelFilter {
+    let index = (level as i8 + verbosity).clamp(0, LevelFilter::max() as i8);
+
+    LevelFilter::iter()
+        .nth(index as usize)
+        .unwrap_or(LevelFilter::max())
+}
+
+fn format_text(record: &Record, journald: bool) -> String {
+    let mut line = String::new();
+
+    if journald {
+        line.push_str(&format!("<{}>", syslog_priority(record.level())));
+    }
+
+    line.push_str(match record.level() {
+        Level::Error => "Error: ",
+        Level::Warn => "Warning: ",
+        Level::Info => "",
+        Level::Debug => "Debug: ",
+        Level::Trace => "Trace: ",
+    });
+
+    // The journal would split multi-line messages into separate entries
+    let message = record.args().to_string();
+    if journald {
+        line.push_str(&message.replace('\n', " "));
+    } else {
+        line.push_str(&message);
+    }
+
+    line
+}
+
+fn format_json(record: &Record, journald: bool) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0);
+
+    let json = serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+
+    if journald {
+        format!("<{}>{}", syslog_priority(record.level()), json)
+    } else {
+        json.to_string()
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let journald = self.journald.load(Ordering::Relaxed);
+
+        let line = if self.json.load(Ordering::Relaxed) {
+            format_json(record, journald)
+        } else {
+            format_text(record, journald)
+        };
+
+        let _ = writeln!(io::stderr().lock(), "{line}");
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Initialize the logger. `verbosity` is the number of levels by which to raise (positive)
+/// or lower (negative) the log level, set using the `-v` and `-q` flags.
+pub fn init(verbosity: i8) {
+    LOGGER.journald.store(is_journald(), Ordering::Relaxed);
+    LOGGER.verbosity.store(verbosity, Ordering::Relaxed);
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(adjust_level(DEFAULT_LEVEL, verbosity));
+    }
+}
+
+/// Apply the logging options from the config.
+pub fn configure(config: &Config) {
+    let level = config.log_level.unwrap_or(DEFAULT_LEVEL);
+    let verbosity = LOGGER.verbosity.load(Ordering::Relaxed);
+
+    log::set_max_level(adjust_level(level, verbosity));
+
+    LOGGER.json.store(
+        config.log_format.unwrap_or_default() == LogFormat::Json,
+        Ordering::Relaxed,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust_level() {
+        assert_eq!(adjust_level(LevelFilter::Info, 0), LevelFilter::Info);
+        assert_eq!(adjust_level(LevelFilter::Info, 1), LevelFilter::Debug);
+        assert_eq!(adjust_level(LevelFilter::Info, 5), LevelFilter::Trace);
+        assert_eq!(adjust_level(LevelFilter::Info, -2), LevelFilter::Error);
+        assert_eq!(adjust_level(LevelFilter::Warn, -5), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_format() {
+        let args = format_args!("Track submitted\nsuccessfully");
+        let record = Record::builder()
+            .args(args)
+            .level(Level::Warn)
+            .target("rescrobbled")
+            .build();
+
+        assert_eq!(
+            format_text(&record, false),
+            "Warning: Track submitted\nsuccessfully"
+        );
+        assert_eq!(
+            format_text(&record, true),
+            "<4>Warning: Track submitted successfully"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(format_json(&record, false).as_str()).unwrap();
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["message"], "Track submitted\nsuccessfully");
+
+        assert!(format_json(&record, true).starts_with("<4>{"));
+    }
+}
@@ -15,36 +15,324 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
+use anyhow::{Context, Result, bail};
+
+use chrono::{DateTime, Local};
+
+use clap::Parser;
+
+use mpris::{Metadata, MetadataValue};
+
+mod cli;
 mod config;
+mod datetime;
 mod filter;
+mod history;
+mod logging;
 mod mainloop;
+mod normalize;
+mod output;
 mod player;
+mod secret_service;
 mod service;
+mod signals;
+mod state;
+mod stream;
+mod systemd;
 mod track;
+mod worker;
 
-use config::load_config;
+use cli::{
+    AuthService, Cli, Command, ConfigCommand, ExportFormat, HistoryArgs, ImportFormat, StatsArgs,
+};
+use config::validate::{Severity, validate};
+use config::{Config, config_path, load_config};
+use datetime::to_timestamp;
+use filter::{FilterResult, filter_metadata};
+use history::{Decision, History, Query};
+use secret_service::SecretService;
 use service::Service;
-
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+use systemd::Notifier;
+use track::Track;
 
 fn main() -> Result<()> {
-    let arg = std::env::args().nth(1);
+    let cli = Cli::parse();
 
-    if let Some("-v" | "--version") = arg.as_deref() {
-        println!("rescrobbled v{VERSION}");
-        return Ok(());
-    }
+    logging::init(cli.verbosity());
+
+    // Created after logging is initialized, so that problems with the
+    // notification socket are logged
+    let notifier = Notifier::from_environment();
 
-    let config = load_config()?;
+    let path = match cli.config {
+        Some(path) => path,
+        None => config_path()?,
+    };
 
-    if let Some("config") = arg.as_deref() {
-        println!("{:#?}", config);
-        return Ok(());
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(path, notifier),
+        Command::Config { command } => config_command(path, command.unwrap_or(ConfigCommand::Show)),
+        Command::Auth {
+            service: AuthService::Lastfm { username },
+        } => Service::login_lastfm(&load(&path)?, username.as_deref()),
+        Command::Auth {
+            service: AuthService::SecretService { name },
+        } => store_secret(&name),
+        Command::History(args) => history(args),
+        Command::Stats(args) => stats(args),
+        Command::Import {
+            file,
+            format,
+            dry_run,
+        } => import(&path, &file, format, dry_run),
+        Command::Export {
+            format,
+            since,
+            until,
+            output,
+        } => export(format, since, until, output.as_deref()),
+        Command::TestFilter {
+            artist,
+            title,
+            album,
+            genre,
+        } => test_filter(&load(&path)?, &artist, &title, album.as_deref(), genre),
     }
+}
+
+/// Load the config file and apply its logging options.
+fn load(path: &Path) -> Result<Config> {
+    let config = load_config(path)?;
+
+    logging::configure(&config);
+
+    Ok(config)
+}
+
+fn run(path: PathBuf, notifier: Notifier) -> Result<()> {
+    let config = load(&path)?;
 
     let services = Service::initialize_all(&config);
 
-    mainloop::run(config, services)
+    mainloop::run(path, config, services, notifier)
+}
+
+fn config_command(path: PathBuf, command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Show => println!("{:#?}", load(&path)?),
+        ConfigCommand::Check { strict } => check_config(&path, strict)?,
+        ConfigCommand::Template => print!("{}", Config::template()),
+        ConfigCommand::Path => println!("{}", path.display()),
+    }
+
+    Ok(())
+}
+
+/// Read a secret from standard input and store it in the Secret Service under `name`.
+fn store_secret(name: &str) -> Result<()> {
+    if io::stdin().is_terminal() {
+        eprint!("Secret for `{name}`: ");
+    }
+
+    let mut secret = String::new();
+    io::stdin()
+        .read_line(&mut secret)
+        .context("Failed to read secret")?;
+
+    let secret = secret.trim();
+    if secret.is_empty() {
+        bail!("The secret must not be empty");
+    }
+
+    SecretService::connect()?.store_config_secret(name, secret)?;
+
+    eprintln!("Stored secret `{name}` in the Secret Service");
+
+    Ok(())
+}
+
+/// Validate the config file and print all problems that were found.
+fn check_config(path: &Path, strict: bool) -> Result<()> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to open config file {}", path.display()))?;
+
+    let (_, diagnostics) = validate(&source, strict);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{}\n", diagnostic.render(path));
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+
+    if errors > 0 {
+        bail!(
+            "{} is invalid ({errors} error(s), {warnings} warning(s))",
+            path.display()
+        );
+    }
+
+    if warnings > 0 {
+        println!("{} is valid ({warnings} warning(s))", path.display());
+    } else {
+        println!("{} is valid", path.display());
+    }
+
+    Ok(())
+}
+
+fn history(args: HistoryArgs) -> Result<()> {
+    let history = History::open()?;
+
+    let entries = history.entries(&Query {
+        since: args.since.map(to_timestamp),
+        until: args.until.map(to_timestamp),
+        artist: args.artist,
+        player: args.player,
+        service: args.service,
+        failed: args.failed,
+        decision: None,
+        limit: (args.limit > 0).then_some(args.limit),
+        without_submissions: false,
+    })?;
+
+    history::list::write(&entries, args.format, &mut io::stdout().lock())
+}
+
+fn stats(args: StatsArgs) -> Result<()> {
+    let history = History::open()?;
+
+    let since = (!args.all).then_some(args.since);
+    let until = args.until.unwrap_or_else(Local::now);
+
+    let entries = history.entries(&Query {
+        since: since.map(to_timestamp),
+        until: Some(to_timestamp(until)),
+        decision: Some(Decision::Scrobbled),
+        // The statistics are computed from the plays only
+        without_submissions: true,
+        ..Default::default()
+    })?;
+
+    let stats = history::stats::compute(&entries, since, until, args.by, args.limit);
+
+    history::stats::write(&stats, args.format, &mut io::stdout().lock())
+}
+
+fn import(path: &Path, file: &Path, format: Option<ImportFormat>, dry_run: bool) -> Result<()> {
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+
+    let plays = history::import::parse(&contents, format)
+        .with_context(|| format!("Failed to parse {}", file.display()))?;
+
+    let history = History::open()?;
+
+    // Don't connect to the services if nothing is submitted
+    let services = if dry_run {
+        Vec::new()
+    } else {
+        let config = load(path)?;
+        let services = Service::initialize_all(&config);
+
+        // Plays that are imported are never submitted to the missing services later
+        let configured =
+            config.lastfm.iter().flatten().count() + config.listenbrainz.iter().flatten().count();
+        if services.len() < configured {
+            bail!(
+                "{} of {configured} scrobbling service(s) are not available, \
+                fix the errors above and try again",
+                configured - services.len()
+            );
+        }
+
+        services
+    };
+
+    history::import::import(&history, &services, plays, dry_run)
+}
+
+fn export(
+    format: ExportFormat,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let history = History::open()?;
+
+    let plays: Vec<_> = history
+        .entries(&Query {
+            since: since.map(to_timestamp),
+            until: until.map(to_timestamp),
+            decision: Some(Decision::Scrobbled),
+            ..Default::default()
+        })?
+        .into_iter()
+        .map(|entry| entry.play)
+        .collect();
+
+    match output {
+        Some(output) => {
+            let mut file = BufWriter::new(
+                File::create(output)
+                    .with_context(|| format!("Failed to create {}", output.display()))?,
+            );
+            history::export::write(&plays, format, &mut file)?;
+            file.flush()?;
+
+            println!(
+                "Exported {} scrobble(s) to {}",
+                plays.len(),
+                output.display()
+            );
+        }
+        None => history::export::write(&plays, format, &mut io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+fn test_filter(
+    config: &Config,
+    artist: &str,
+    title: &str,
+    album: Option<&str>,
+    genres: Vec<String>,
+) -> Result<()> {
+    let track = Track::new(artist, title, album);
+
+    let track = match config.normalize {
+        Some(ref normalize) => {
+            let track = normalize::normalize(track, normalize, &config.title_separators());
+            println!("Normalized: {} - {}", track.artist(), track.title());
+            track
+        }
+        None => track,
+    };
+
+    let genres = genres.into_iter().map(MetadataValue::String).collect();
+    let metadata = Metadata::from(HashMap::from([(
+        "xesam:genre".to_owned(),
+        MetadataValue::Array(genres),
+    )]));
+
+    match filter_metadata(config, track, &metadata)? {
+        FilterResult::Filtered(track) => {
+            println!("Artist: {}", track.artist());
+            println!("Title: {}", track.title());
+            println!("Album: {}", track.album().unwrap_or(""));
+        }
+        FilterResult::NotFiltered(_) => println!("No filter script is configured"),
+        FilterResult::Ignored => println!("Track would be ignored"),
+    }
+
+    Ok(())
 }
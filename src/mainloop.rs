@@ -13,65 +13,463 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result, anyhow};
 
-use mpris::{PlaybackStatus, PlayerFinder};
+use log::{error, info, trace};
 
-use crate::config::Config;
+use mpris::{Metadata, PlaybackStatus, Player, PlayerFinder};
+
+use crate::config::{
+    Config, ConfigWatcher, NormalizeConfig, Thresholds, UnknownLength, read_config,
+};
 use crate::filter::{FilterResult, filter_metadata};
+use crate::history::{Decision, History, Play};
+use crate::logging;
+use crate::normalize;
 use crate::player;
 use crate::service::Service;
+use crate::signals::Signals;
+use crate::state::{Session, State, unix_timestamp};
+use crate::stream;
+use crate::systemd::Notifier;
 use crate::track::Track;
+use crate::worker::Worker;
 
 const POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-const MIN_LENGTH: Duration = Duration::from_secs(30);
-const MIN_PLAY_TIME: Duration = Duration::from_secs(4 * 60);
+/// Tracks that are this long or shorter are not scrobbled, unless `min-track-length` is set.
+const DEFAULT_MIN_TRACK_LENGTH: Duration = Duration::from_secs(30);
+/// By default, tracks are scrobbled after playing for half their length,
+/// or for 4 minutes, whichever comes first (as recommended by Last.fm).
+const DEFAULT_SCROBBLE_PERCENT: u8 = 50;
+const DEFAULT_SCROBBLE_MAX_SECONDS: Duration = Duration::from_secs(4 * 60);
 
-fn get_min_play_time(config: &Config, track_length: Duration) -> Duration {
-    config.min_play_time.unwrap_or_else(|| {
-        if (track_length / 2) < MIN_PLAY_TIME {
-            track_length / 2
-        } else {
-            MIN_PLAY_TIME
+/// A track without a known length is considered to be repeated if its position jumps back
+/// to less than this from the start.
+const RESTART_POSITION: Duration = Duration::from_secs(5);
+
+fn get_min_play_time(thresholds: &Thresholds, length: Option<Duration>) -> Duration {
+    if let Some(min_play_time) = thresholds.min_play_time {
+        return min_play_time;
+    }
+
+    let min_track_length = thresholds
+        .min_track_length
+        .unwrap_or(DEFAULT_MIN_TRACK_LENGTH);
+
+    // Tracks of unknown length are treated like the shortest track that can be scrobbled
+    let length = match length {
+        Some(length) => length,
+        None => match thresholds.unknown_length_play_time {
+            Some(play_time) => return play_time,
+            None => min_track_length,
+        },
+    };
+
+    let percent = thresholds
+        .scrobble_percent
+        .unwrap_or(DEFAULT_SCROBBLE_PERCENT)
+        .min(100);
+
+    (length * u32::from(percent) / 100).min(
+        thresholds
+            .scrobble_max_seconds
+            .unwrap_or(DEFAULT_SCROBBLE_MAX_SECONDS),
+    )
+}
+
+/// Determine if a track has been playing long enough to be scrobbled.
+fn should_scrobble(thresholds: &Thresholds, length: Option<Duration>, play_time: Duration) -> bool {
+    if length.is_none() && thresholds.unknown_length.unwrap_or_default() != UnknownLength::PlayTime
+    {
+        return false;
+    }
+
+    let min_track_length = thresholds
+        .min_track_length
+        .unwrap_or(DEFAULT_MIN_TRACK_LENGTH);
+
+    length.is_none_or(|length| length > min_track_length)
+        && play_time > get_min_play_time(thresholds, length)
+}
+
+/// Determine if a track of unknown length should be scrobbled when it stops playing,
+/// using the measured play time as its length.
+fn should_scrobble_ended(
+    thresholds: &Thresholds,
+    length: Option<Duration>,
+    play_time: Duration,
+) -> bool {
+    let min_track_length = thresholds
+        .min_track_length
+        .unwrap_or(DEFAULT_MIN_TRACK_LENGTH);
+
+    length.is_none()
+        && thresholds.unknown_length == Some(UnknownLength::TrackChange)
+        && play_time > min_track_length
+}
+
+/// Determine if a track without a known length was restarted, based on its position.
+fn is_restarted(previous_position: Option<Duration>, position: Option<Duration>) -> bool {
+    match (previous_position, position) {
+        (Some(previous), Some(position)) => {
+            position < RESTART_POSITION && previous > position + RESTART_POSITION
         }
-    })
+        _ => false,
+    }
 }
 
-pub fn run(config: Config, services: Vec<Service>) -> Result<()> {
+/// Determine if a player is playing an internet stream, either because its `[[player]]`
+/// definition says so, or because the track has a network URL and no length.
+fn is_stream(config: &Config, player: &Player, metadata: &Metadata) -> bool {
+    player::is_stream_player(config, player).unwrap_or_else(|| stream::is_stream(metadata))
+}
+
+/// Get the track that is playing. For streams, the artist is parsed from the title if needed.
+/// Afterwards, the track is normalized if that is enabled for the player.
+fn track_of(config: &Config, player: &Player, metadata: &Metadata) -> Track {
+    let separators = config.title_separators();
+
+    let track = if is_stream(config, player, metadata) {
+        stream::track_from_metadata(metadata, &separators)
+    } else {
+        Track::from_metadata(metadata)
+    };
+
+    if !player::is_normalized(config, player) {
+        return track;
+    }
+
+    match config.normalize {
+        Some(ref normalize) => normalize::normalize(track, normalize, &separators),
+        None => normalize::normalize(track, &NormalizeConfig::default(), &separators),
+    }
+}
+
+/// Get the scrobble thresholds for a player. Songs on a stream are scrobbled
+/// when the next one starts, unless `unknown-length` is set.
+fn thresholds_of(config: &Config, player: &Player, metadata: &Metadata) -> Thresholds {
+    let mut thresholds = player::thresholds(config, player);
+
+    if is_stream(config, player, metadata) {
+        thresholds.unknown_length = thresholds
+            .unknown_length
+            .or(Some(UnknownLength::TrackChange));
+    }
+
+    thresholds
+}
+
+/// Get the length of a track from its metadata, if it is known and nonzero.
+fn length_of(metadata: &Metadata) -> Option<Duration> {
+    metadata
+        .length()
+        .and_then(|length| if length.is_zero() { None } else { Some(length) })
+}
+
+/// Describe a track that was played, to add it to the history.
+fn play_of(
+    player: &Player,
+    track: Track,
+    metadata: &Metadata,
+    track_start: &SystemTime,
+    play_time: Duration,
+    decision: Decision,
+) -> Play {
+    Play {
+        track,
+        player: player.bus_name().to_owned(),
+        started_at: unix_timestamp(track_start),
+        play_time,
+        length: length_of(metadata),
+        decision,
+    }
+}
+
+/// Handle a track that stopped playing before it was scrobbled. Tracks of unknown length
+/// are scrobbled now if `unknown-length = "track-change"`, other tracks were skipped.
+fn track_ended(
+    config: &Config,
+    worker: &Worker,
+    player: &Player,
+    track: &Track,
+    metadata: &Metadata,
+    track_start: &SystemTime,
+    play_time: Duration,
+) {
+    if track.is_empty() || play_time.is_zero() {
+        return;
+    }
+
+    if should_scrobble_ended(
+        &thresholds_of(config, player, metadata),
+        length_of(metadata),
+        play_time,
+    ) {
+        let play = play_of(
+            player,
+            track.clone(),
+            metadata,
+            track_start,
+            play_time,
+            Decision::Scrobbled,
+        );
+
+        scrobble(config, worker, play, metadata, track_start);
+        return;
+    }
+
+    let play = play_of(
+        player,
+        track.clone(),
+        metadata,
+        track_start,
+        play_time,
+        Decision::Skipped,
+    );
+
+    worker.record(play);
+}
+
+/// Run the filter script on a track that has played long enough,
+/// add it to the history and submit it.
+fn scrobble(
+    config: &Config,
+    worker: &Worker,
+    mut play: Play,
+    metadata: &Metadata,
+    track_start: &SystemTime,
+) {
+    let track_start = config
+        .use_track_start_timestamp
+        .unwrap_or(false)
+        .then_some(*track_start);
+
+    match filter_metadata(config, play.track.clone(), metadata) {
+        Ok(FilterResult::Filtered(track)) | Ok(FilterResult::NotFiltered(track))
+            if !track.artist().is_empty() =>
+        {
+            play.track = track;
+
+            worker.scrobble(play, track_start);
+        }
+        Ok(_) => {
+            play.decision = Decision::Ignored;
+
+            worker.record(play);
+        }
+        Err(err) => error!("{:#}", err),
+    }
+}
+
+/// Open the history database, and move the pending scrobbles saved in the state file into it.
+/// If the database cannot be opened, a temporary history is used.
+fn open_history(state: &mut State) -> Result<History> {
+    let mut history = match History::open() {
+        Ok(history) => history,
+        Err(err) => {
+            error!(
+                "{:#}",
+                err.context(
+                    "Failed to open the history database, \
+                    scrobbles that fail to submit are only retried until rescrobbled exits"
+                )
+            );
+            return History::open_in_memory();
+        }
+    };
+
+    if !state.pending.is_empty() {
+        match history.import_pending(&state.pending) {
+            Ok(()) => {
+                info!(
+                    "Moved {} pending scrobble(s) to the history database",
+                    state.pending.len()
+                );
+                state.pending.clear();
+            }
+            Err(err) => error!("{:#}", err),
+        }
+    }
+
+    Ok(history)
+}
+
+/// Determine if the config should be reloaded, either because `SIGHUP` was received
+/// or because the config file was modified and `watch-config` is enabled.
+fn should_reload(config: &Config, signals: &Signals, watcher: &mut ConfigWatcher) -> bool {
+    // Always poll the watcher, so enabling `watch-config` later does not
+    // immediately trigger a reload because of an old modification
+    let modified = watcher.changed();
+
+    signals.take_reload() || (config.watch_config.unwrap_or(false) && modified)
+}
+
+/// Reload the config file and re-initialize the services whose settings changed.
+/// If the new config cannot be loaded, the current config is kept.
+fn reload(path: &Path, config: &mut Arc<Config>, worker: &Worker, notifier: &Notifier) {
+    info!("Reloading config...");
+
+    notifier.reloading();
+
+    match read_config(path) {
+        Ok(new_config) => {
+            logging::configure(&new_config);
+
+            *config = Arc::new(new_config);
+            worker.reload(config.clone());
+
+            info!("Config reloaded successfully");
+        }
+        Err(err) => error!("{:#}", err.context("Failed to reload config")),
+    }
+
+    notifier.ready();
+}
+
+/// Wait for an active player, reloading the config while waiting if necessary.
+/// Returns `None` if a shutdown was requested while waiting.
+fn wait_for_player(
+    config: &mut Arc<Config>,
+    worker: &Worker,
+    finder: &PlayerFinder,
+    signals: &Signals,
+    watcher: &mut ConfigWatcher,
+    notifier: &mut Notifier,
+) -> Option<Player> {
+    notifier.status("Looking for an active MPRIS player");
+
+    loop {
+        let player = player::wait_for_player(config, finder, || {
+            notifier.watchdog();
+
+            !signals.should_exit() && !should_reload(config, signals, watcher)
+        });
+
+        match player {
+            Some(player) => {
+                notifier.status(&format!("Found active player {}", player.identity()));
+                return Some(player);
+            }
+            None if signals.should_exit() => return None,
+            None => reload(watcher.path(), config, worker, notifier),
+        }
+    }
+}
+
+pub fn run(
+    config_path: PathBuf,
+    config: Config,
+    services: Vec<Service>,
+    mut notifier: Notifier,
+) -> Result<()> {
     let finder = PlayerFinder::new()
         .map_err(|err| anyhow!("{}", err))
         .context("Failed to connect to D-Bus")?;
 
-    println!("Looking for an active MPRIS player...");
+    let signals = Signals::register()?;
+    let mut watcher = ConfigWatcher::new(config_path);
+
+    let mut state = State::load().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        State::default()
+    });
+
+    let history = open_history(&mut state)?;
 
-    let mut player = player::wait_for_player(&config, &finder);
+    let mut config = Arc::new(config);
+    let worker = Worker::spawn(services, history)?;
 
-    println!("Found active player {}", player.identity());
+    notifier.ready();
+
+    info!("Looking for an active MPRIS player...");
+
+    let Some(mut player) = wait_for_player(
+        &mut config,
+        &worker,
+        &finder,
+        &signals,
+        &mut watcher,
+        &mut notifier,
+    ) else {
+        return shut_down(state, worker, &notifier);
+    };
+
+    info!("Found active player {}", player.identity());
 
     let mut previous_track = Track::default();
+    let mut previous_metadata = Metadata::default();
 
     let mut timer = Instant::now();
     let mut current_play_time = Duration::from_secs(0);
     let mut scrobbled_current_song = false;
     let mut track_start = SystemTime::now();
+    let mut previous_position = None;
 
     loop {
-        if !player::is_active(&player) {
-            println!(
-                "----\n\
-                Player {} stopped, looking for a new MPRIS player...",
-                player.identity()
+        if signals.should_exit() {
+            break;
+        }
+
+        notifier.watchdog();
+
+        let mut allowed = true;
+
+        if should_reload(&config, &signals, &mut watcher) {
+            reload(watcher.path(), &mut config, &worker, &notifier);
+
+            allowed = player::is_allowed(&config, &player);
+        }
+
+        if !allowed || !player::is_active(&player) {
+            if !scrobbled_current_song {
+                track_ended(
+                    &config,
+                    &worker,
+                    &player,
+                    &previous_track,
+                    &previous_metadata,
+                    &track_start,
+                    current_play_time,
+                );
+            }
+
+            info!(
+                "Player {} {}, looking for a new MPRIS player...",
+                player.identity(),
+                if allowed {
+                    "stopped"
+                } else {
+                    "is no longer allowed"
+                },
             );
 
-            player = player::wait_for_player(&config, &finder);
+            let new_player = wait_for_player(
+                &mut config,
+                &worker,
+                &finder,
+                &signals,
+                &mut watcher,
+                &mut notifier,
+            );
 
-            println!("Found active player {}", player.identity());
+            match new_player {
+                Some(new_player) => player = new_player,
+                None => {
+                    previous_track.clear();
+                    break;
+                }
+            }
+
+            info!("Found active player {}", player.identity());
 
             previous_track.clear();
+            worker.now_playing(None);
 
             timer = Instant::now();
             current_play_time = Duration::from_secs(0);
@@ -85,12 +483,14 @@ pub fn run(config: Config, services: Vec<Service>) -> Result<()> {
 
         match status {
             Ok(PlaybackStatus::Playing) => {}
-            Ok(_) => {
+            Ok(status) => {
+                trace!("Player {} is not playing ({:?})", player.identity(), status);
+
                 thread::sleep(POLL_INTERVAL);
                 continue;
             }
             Err(err) => {
-                eprintln!("{:?}", err);
+                error!("{:#}", err);
 
                 thread::sleep(POLL_INTERVAL);
                 continue;
@@ -105,92 +505,262 @@ pub fn run(config: Config, services: Vec<Service>) -> Result<()> {
         let metadata = match metadata {
             Ok(metadata) => metadata,
             Err(err) => {
-                eprintln!("{:?}", err);
+                error!("{:#}", err);
 
                 thread::sleep(POLL_INTERVAL);
                 continue;
             }
         };
 
-        let current_track = Track::from_metadata(&metadata);
+        let current_track = track_of(&config, &player, &metadata);
 
-        let length = metadata
-            .length()
-            .and_then(|length| if length.is_zero() { None } else { Some(length) });
+        let length = length_of(&metadata);
 
         if current_track == previous_track {
-            if !scrobbled_current_song {
-                let min_play_time = get_min_play_time(&config, length.unwrap_or(MIN_LENGTH));
-
-                if length.map(|length| length > MIN_LENGTH).unwrap_or(true)
-                    && current_play_time > min_play_time
-                {
-                    let track_start = config
-                        .use_track_start_timestamp
-                        .unwrap_or(false)
-                        .then_some(&track_start);
-
-                    match filter_metadata(&config, current_track, &metadata) {
-                        Ok(FilterResult::Filtered(track))
-                        | Ok(FilterResult::NotFiltered(track)) => {
-                            for service in services.iter() {
-                                match service.submit(&track, track_start) {
-                                    Ok(()) => {
-                                        println!("Track submitted to {} successfully", service)
-                                    }
-                                    Err(err) => eprintln!("{:?}", err),
-                                }
-                            }
-                        }
-                        Ok(FilterResult::Ignored) => {}
-                        Err(err) => eprintln!("{:?}", err),
-                    }
-
-                    scrobbled_current_song = true;
-                }
-            } else if length
-                .map(|length| current_play_time >= length)
-                .unwrap_or(false)
+            let position = player.get_position().ok();
+
+            if !scrobbled_current_song
+                && should_scrobble(
+                    &thresholds_of(&config, &player, &metadata),
+                    length,
+                    current_play_time,
+                )
             {
+                let play = play_of(
+                    &player,
+                    current_track,
+                    &metadata,
+                    &track_start,
+                    current_play_time,
+                    Decision::Scrobbled,
+                );
+
+                scrobble(&config, &worker, play, &metadata, &track_start);
+
+                scrobbled_current_song = true;
+            } else if (scrobbled_current_song
+                && length.is_some_and(|length| current_play_time >= length))
+                || (length.is_none() && is_restarted(previous_position, position))
+            {
+                if !scrobbled_current_song {
+                    track_ended(
+                        &config,
+                        &worker,
+                        &player,
+                        &current_track,
+                        &metadata,
+                        &track_start,
+                        current_play_time,
+                    );
+                }
+
                 current_play_time = Duration::from_secs(0);
                 scrobbled_current_song = false;
                 track_start = SystemTime::now();
             }
 
+            previous_position = position;
+
             current_play_time += timer.elapsed();
             timer = Instant::now();
         } else {
+            if !scrobbled_current_song {
+                track_ended(
+                    &config,
+                    &worker,
+                    &player,
+                    &previous_track,
+                    &previous_metadata,
+                    &track_start,
+                    current_play_time,
+                );
+            }
+
             previous_track.clone_from(&current_track);
+            previous_metadata = metadata.clone();
+            previous_position = None;
 
             timer = Instant::now();
             current_play_time = Duration::from_secs(0);
             scrobbled_current_song = false;
             track_start = SystemTime::now();
 
-            print!(
-                "----\n\
-                Now playing: {} - {}",
-                current_track.artist(),
-                current_track.title(),
-            );
-            if let Some(album) = current_track.album() {
-                println!(" ({album})");
+            // Restore the play time if rescrobbled was restarted while this track was playing
+            if let Some(session) = state.session.take()
+                && session.player == player.bus_name()
+                && session.track == current_track
+                && session.is_recent()
+            {
+                current_play_time = Duration::from_millis(session.play_time);
+                scrobbled_current_song = session.scrobbled;
+                track_start = UNIX_EPOCH + Duration::from_secs(session.track_start);
+            }
+
+            match current_track.album() {
+                Some(album) => info!(
+                    "Now playing: {} - {} ({album})",
+                    current_track.artist(),
+                    current_track.title(),
+                ),
+                None => info!(
+                    "Now playing: {} - {}",
+                    current_track.artist(),
+                    current_track.title(),
+                ),
             }
 
+            notifier.status(&format!(
+                "Now playing: {} - {}",
+                current_track.artist(),
+                current_track.title()
+            ));
+
             match filter_metadata(&config, current_track, &metadata) {
-                Ok(FilterResult::Filtered(track)) | Ok(FilterResult::NotFiltered(track)) => {
-                    for service in services.iter() {
-                        match service.now_playing(&track) {
-                            Ok(()) => println!("Status updated on {} successfully", service),
-                            Err(err) => eprintln!("{:?}", err),
-                        }
-                    }
+                Ok(FilterResult::Filtered(track)) | Ok(FilterResult::NotFiltered(track))
+                    if !track.artist().is_empty() =>
+                {
+                    worker.now_playing(Some(track));
+                }
+                Ok(FilterResult::Filtered(_)) | Ok(FilterResult::NotFiltered(_)) => {
+                    info!("Track has no artist, ignoring it");
+                    worker.now_playing(None);
+                }
+                Ok(FilterResult::Ignored) => {
+                    info!("Track ignored");
+                    worker.now_playing(None);
+                }
+                Err(err) => {
+                    error!("{:#}", err);
+                    worker.now_playing(None);
                 }
-                Ok(FilterResult::Ignored) => println!("Track ignored"),
-                Err(err) => eprintln!("{:?}", err),
             }
         }
 
         thread::sleep(POLL_INTERVAL);
     }
+
+    if !previous_track.is_empty() {
+        let thresholds = thresholds_of(&config, &player, &previous_metadata);
+        let length = length_of(&previous_metadata);
+
+        // Tracks of unknown length that are scrobbled when they end, like streams,
+        // also end when rescrobbled exits
+        if !scrobbled_current_song
+            && config.scrobble_on_exit.unwrap_or(true)
+            && (should_scrobble(&thresholds, length, current_play_time)
+                || should_scrobble_ended(&thresholds, length, current_play_time))
+        {
+            let play = play_of(
+                &player,
+                previous_track.clone(),
+                &previous_metadata,
+                &track_start,
+                current_play_time,
+                Decision::Scrobbled,
+            );
+
+            scrobble(&config, &worker, play, &previous_metadata, &track_start);
+
+            scrobbled_current_song = true;
+        }
+
+        state.session = Some(Session {
+            player: player.bus_name().to_owned(),
+            track: previous_track,
+            play_time: current_play_time.as_millis() as u64,
+            track_start: unix_timestamp(&track_start),
+            scrobbled: scrobbled_current_song,
+            saved_at: unix_timestamp(&SystemTime::now()),
+        });
+    }
+
+    shut_down(state, worker, &notifier)
+}
+
+/// Wait for the queued submissions to finish, and persist the state before exiting.
+fn shut_down(mut state: State, worker: Worker, notifier: &Notifier) -> Result<()> {
+    info!("Shutting down...");
+
+    notifier.stopping();
+
+    // Scrobbles that could not be added to the history are moved to it at the next start
+    state.pending.extend(worker.stop());
+
+    state.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_default_thresholds() {
+        let thresholds = Thresholds::default();
+
+        assert!(!should_scrobble(&thresholds, Some(secs(200)), secs(99)));
+        assert!(should_scrobble(&thresholds, Some(secs(200)), secs(101)));
+        // Long tracks are scrobbled after 4 minutes
+        assert!(should_scrobble(&thresholds, Some(secs(3600)), secs(241)));
+        // Short tracks are never scrobbled
+        assert!(!should_scrobble(&thresholds, Some(secs(30)), secs(30)));
+        // Tracks of unknown length are treated as 30 seconds long
+        assert!(should_scrobble(&thresholds, None, secs(16)));
+    }
+
+    #[test]
+    fn test_configured_thresholds() {
+        let thresholds = Thresholds {
+            min_track_length: Some(secs(60)),
+            scrobble_percent: Some(80),
+            scrobble_max_seconds: Some(secs(600)),
+            unknown_length_play_time: Some(secs(120)),
+            ..Default::default()
+        };
+
+        assert!(!should_scrobble(&thresholds, Some(secs(45)), secs(45)));
+        assert!(!should_scrobble(&thresholds, Some(secs(300)), secs(239)));
+        assert!(should_scrobble(&thresholds, Some(secs(300)), secs(241)));
+        assert!(should_scrobble(&thresholds, Some(secs(3600)), secs(601)));
+        assert!(!should_scrobble(&thresholds, None, secs(100)));
+        assert!(should_scrobble(&thresholds, None, secs(121)));
+
+        // `min-play-time` ignores the track length
+        let thresholds = Thresholds {
+            min_play_time: Some(secs(10)),
+            ..thresholds
+        };
+        assert!(should_scrobble(&thresholds, Some(secs(3600)), secs(11)));
+        assert!(!should_scrobble(&thresholds, Some(secs(45)), secs(45)));
+    }
+
+    #[test]
+    fn test_unknown_length() {
+        let mut thresholds = Thresholds {
+            unknown_length: Some(UnknownLength::Never),
+            min_play_time: Some(secs(10)),
+            ..Default::default()
+        };
+        assert!(!should_scrobble(&thresholds, None, secs(3600)));
+        assert!(!should_scrobble_ended(&thresholds, None, secs(3600)));
+
+        thresholds.unknown_length = Some(UnknownLength::TrackChange);
+        assert!(!should_scrobble(&thresholds, None, secs(3600)));
+        assert!(should_scrobble_ended(&thresholds, None, secs(31)));
+        assert!(!should_scrobble_ended(&thresholds, None, secs(29)));
+        // Tracks with a length were skipped
+        assert!(!should_scrobble_ended(
+            &thresholds,
+            Some(secs(60)),
+            secs(40)
+        ));
+
+        assert!(is_restarted(Some(secs(200)), Some(secs(1))));
+        assert!(!is_restarted(Some(secs(200)), Some(secs(190))));
+        assert!(!is_restarted(Some(secs(3)), Some(secs(0))));
+        assert!(!is_restarted(None, Some(secs(0))));
+    }
 }
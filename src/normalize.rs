@@ -0,0 +1,293 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Built-in cleanup of the artists and titles reported by browsers and streaming sites,
+//! like `ArtistVEVO` and `Artist - Song (Official Video) [4K]`.
+
+use std::fmt;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::NormalizeConfig;
+use crate::stream;
+use crate::track::Track;
+
+/// Junk that is removed from titles, unless `junk-patterns` is set.
+pub const DEFAULT_JUNK_PATTERNS: &[&str] = &[
+    r"official\s+(music\s+|lyric\s+)?(video|audio)",
+    r"(official\s+)?lyrics?(\s+video)?",
+    r"(official\s+)?visuali[sz]er",
+    r"(video\s+)?clip\s+officiel",
+    r"music\s+video",
+    r"audio|video",
+    r"hd|hq|4k|1080p|720p",
+    r"explicit",
+    r"(\d{4}\s+)?remaster(ed)?(\s+\d{4})?(\s+version)?",
+];
+
+/// Suffixes that are removed from artists, unless `channel-suffixes` is set.
+pub const DEFAULT_CHANNEL_SUFFIXES: &[&str] = &[" - Topic", "VEVO"];
+
+static DEFAULT_JUNK: LazyLock<JunkPatterns> = LazyLock::new(|| {
+    JunkPatterns::new(
+        DEFAULT_JUNK_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .collect(),
+    )
+    .expect("default junk patterns should be valid")
+});
+
+/// Regex patterns of junk to remove from titles, compiled when the config is loaded.
+#[derive(Clone)]
+pub struct JunkPatterns {
+    patterns: Vec<String>,
+    regex: Regex,
+}
+
+impl JunkPatterns {
+    /// Compile the patterns into a regex that matches them (ignoring case) between parentheses
+    /// or brackets, or after a dash at the end of the title.
+    pub fn new(patterns: Vec<String>) -> Result<Self, regex::Error> {
+        // Check the patterns separately, for clearer errors
+        for pattern in &patterns {
+            Regex::new(pattern)?;
+        }
+
+        let alternatives = patterns
+            .iter()
+            .map(|pattern| format!("(?:{pattern})"))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        let regex = Regex::new(&format!(
+            r"(?i)\s*[(\[]\s*(?:{alternatives})\s*[)\]]|\s+[-–—]\s+(?:{alternatives})\s*$"
+        ))?;
+
+        Ok(Self { patterns, regex })
+    }
+
+    /// Remove all junk from a title.
+    fn strip(&self, title: &str) -> String {
+        self.regex.replace_all(title, "").trim().to_owned()
+    }
+}
+
+impl fmt::Debug for JunkPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.patterns).finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for JunkPatterns {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        Self::new(Vec::deserialize(de)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for JunkPatterns {
+    fn serialize<S: Serializer>(&self, se: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(se)
+    }
+}
+
+/// Remove a channel suffix from an artist, like `VEVO` or ` - Topic`.
+/// Returns `None` if the artist does not end with one of the suffixes.
+///
+/// Channel names often run the words of the artist together, like `RickAstleyVEVO`;
+/// if the suffix was attached directly to such a name, it is split into words at
+/// its capital letters (`Rick Astley`). Names like `deadmau5` or `ABBA` are left as is.
+fn strip_channel_suffix<S: AsRef<str>>(artist: &str, suffixes: &[S]) -> Option<String> {
+    let (artist, suffix) = suffixes.iter().find_map(|suffix| {
+        let suffix = suffix.as_ref();
+        artist.strip_suffix(suffix).map(|artist| (artist, suffix))
+    })?;
+
+    if artist.trim().is_empty() {
+        return None;
+    }
+
+    if suffix.starts_with(char::is_whitespace) || artist.contains(char::is_whitespace) {
+        return Some(artist.trim().to_owned());
+    }
+
+    let mut words = String::with_capacity(artist.len());
+    let mut previous = None;
+    for c in artist.chars() {
+        if previous.is_some_and(char::is_lowercase) && c.is_uppercase() {
+            words.push(' ');
+        }
+        words.push(c);
+        previous = Some(c);
+    }
+
+    Some(words)
+}
+
+/// Clean up a track, using the steps that are enabled in the `[normalize]` section.
+///
+/// Channel suffixes are removed from the artist and junk is removed from the title.
+/// Then, if the track has no artist or the artist was a channel name (which is typical
+/// for videos), the artist is split off the title using the `stream-title-separators`.
+pub fn normalize<S: AsRef<str>>(track: Track, config: &NormalizeConfig, separators: &[S]) -> Track {
+    let mut artist = track.artist().to_owned();
+    let mut title = track.title().to_owned();
+    let mut is_channel = false;
+
+    if config.strip_channel_suffixes.unwrap_or(true) {
+        let stripped = match config.channel_suffixes {
+            Some(ref suffixes) => strip_channel_suffix(&artist, suffixes),
+            None => strip_channel_suffix(&artist, DEFAULT_CHANNEL_SUFFIXES),
+        };
+
+        if let Some(stripped) = stripped {
+            artist = stripped;
+            is_channel = true;
+        }
+    }
+
+    if config.strip_junk.unwrap_or(true) {
+        let junk = config.junk_patterns.as_ref().unwrap_or(&DEFAULT_JUNK);
+        let stripped = junk.strip(&title);
+
+        // Keep titles that consist only of "junk", like a song called "Video"
+        if !stripped.is_empty() {
+            title = stripped;
+        }
+    }
+
+    if config.split_artist.unwrap_or(true)
+        && (artist.is_empty() || is_channel)
+        && let Some((split_artist, split_title)) = stream::split_title(&title, separators)
+    {
+        (artist, title) = (split_artist.to_owned(), split_title.to_owned());
+    }
+
+    Track::new(&artist, &title, track.album())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(artist: &str, title: &str, album: Option<&str>) -> Track {
+        super::normalize(
+            Track::new(artist, title, album),
+            &NormalizeConfig::default(),
+            stream::DEFAULT_SEPARATORS,
+        )
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(
+                "RickAstleyVEVO",
+                "Rick Astley - Never Gonna Give You Up (Official Music Video) [4K]",
+                None
+            ),
+            Track::new("Rick Astley", "Never Gonna Give You Up", None)
+        );
+        assert_eq!(
+            normalize("RickAstleyVEVO", "Never Gonna Give You Up", None),
+            Track::new("Rick Astley", "Never Gonna Give You Up", None)
+        );
+        assert_eq!(
+            normalize("deadmau5VEVO", "Strobe", None),
+            Track::new("deadmau5", "Strobe", None)
+        );
+        assert_eq!(
+            normalize("Radiohead - Topic", "Karma Police", None),
+            Track::new("Radiohead", "Karma Police", None)
+        );
+        assert_eq!(
+            normalize("", "Daft Punk - One More Time (Lyrics)", None),
+            Track::new("Daft Punk", "One More Time", None)
+        );
+
+        // Tracks with a real artist are not split, but junk is still removed
+        assert_eq!(
+            normalize("Blur", "Song 2 - Live", None),
+            Track::new("Blur", "Song 2 - Live", None)
+        );
+        assert_eq!(
+            normalize("Daft Punk", "One More Time (Official Video)", None),
+            Track::new("Daft Punk", "One More Time", None)
+        );
+        assert_eq!(
+            normalize(
+                "The Beatles",
+                "Here Comes The Sun - Remastered 2009",
+                Some("Abbey Road")
+            ),
+            Track::new("The Beatles", "Here Comes The Sun", Some("Abbey Road"))
+        );
+        assert_eq!(
+            normalize("Blur", "Song 2 - Live", Some("Live at Wembley")),
+            Track::new("Blur", "Song 2 - Live", Some("Live at Wembley"))
+        );
+
+        // Parentheses that are part of the title are kept
+        assert_eq!(
+            normalize("Green Day", "Good Riddance (Time of Your Life)", None),
+            Track::new("Green Day", "Good Riddance (Time of Your Life)", None)
+        );
+        assert_eq!(
+            normalize("The Buggles", "Video", Some("The Age of Plastic")).title(),
+            "Video"
+        );
+    }
+
+    #[test]
+    fn test_steps() {
+        let config = NormalizeConfig {
+            split_artist: Some(false),
+            strip_channel_suffixes: Some(false),
+            junk_patterns: Some(JunkPatterns::new(vec![r"live\s+at\s+[^)]*".to_owned()]).unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            super::normalize(
+                Track::new(
+                    "BlurVEVO",
+                    "Blur - Song 2 (Live at Glastonbury 1998) (HD)",
+                    None
+                ),
+                &config,
+                stream::DEFAULT_SEPARATORS,
+            ),
+            Track::new("BlurVEVO", "Blur - Song 2 (HD)", None)
+        );
+
+        let config = NormalizeConfig {
+            strip_junk: Some(false),
+            channel_suffixes: Some(vec![" Official".to_owned()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            super::normalize(
+                Track::new("Blur Official", "Blur / Song 2 (HD)", None),
+                &config,
+                &[" / "],
+            ),
+            Track::new("Blur", "Song 2 (HD)", None)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Helpers for printing command output.
+
+use std::io::{self, Write};
+
+/// A plain text table whose columns are aligned to their widest cell.
+pub struct Table {
+    header: Vec<String>,
+    right_aligned: Vec<bool>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Self {
+            header: header.iter().map(|&cell| cell.to_owned()).collect(),
+            right_aligned: vec![false; header.len()],
+            rows: Vec::new(),
+        }
+    }
+
+    /// Align the given column to the right, e.g. for numbers.
+    pub fn right_align(mut self, column: usize) -> Self {
+        self.right_aligned[column] = true;
+        self
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut widths: Vec<usize> = self.header.iter().map(|cell| width(cell)).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(self::width(cell));
+            }
+        }
+
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            let mut line = String::new();
+
+            for (column, cell) in row.iter().enumerate() {
+                if column > 0 {
+                    line.push_str("  ");
+                }
+
+                let padding = " ".repeat(widths[column] - width(cell));
+
+                if self.right_aligned[column] {
+                    line.push_str(&padding);
+                    line.push_str(cell);
+                } else {
+                    line.push_str(cell);
+                    line.push_str(&padding);
+                }
+            }
+
+            writeln!(out, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn width(cell: &str) -> usize {
+    cell.chars().count()
+}
+
+/// Write a line of comma-separated values, quoting fields where necessary.
+pub fn write_csv_row<S: AsRef<str>>(out: &mut impl Write, fields: &[S]) -> io::Result<()> {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect();
+
+    writeln!(out, "{}", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["Artist", "Plays", "Album"]).right_align(1);
+        table.push(vec![
+            "Sigur Rós".to_owned(),
+            "12".to_owned(),
+            "()".to_owned(),
+        ]);
+        table.push(vec!["Air".to_owned(), "3".to_owned(), String::new()]);
+
+        let mut out = Vec::new();
+        table.write(&mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Artist     Plays  Album\n\
+            Sigur Rós     12  ()\n\
+            Air            3\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut out = Vec::new();
+        write_csv_row(&mut out, &["Crosby, Stills & Nash", "Say \"hi\"", ""]).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"Crosby, Stills & Nash\",\"Say \"\"hi\"\"\",\n"
+        );
+    }
+}
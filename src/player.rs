@@ -19,12 +19,17 @@ use std::time::Duration;
 use mpris::{PlaybackStatus, Player, PlayerFinder};
 use regex::RegexSet;
 
-use crate::config::Config;
+use crate::config::{Config, PlayerConfig, Thresholds};
 
 const INIT_WAIT_TIME: Duration = Duration::from_secs(1);
 
 const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
 
+/// The unique part of a D-Bus bus name, i.e. the part after `org.mpris.MediaPlayer2.`.
+pub fn short_name(bus_name: &str) -> &str {
+    bus_name.trim_start_matches(BUS_NAME_PREFIX)
+}
+
 /// Determine if a player is running and actually playing music.
 pub fn is_active(player: &Player) -> bool {
     if !player.is_running() {
@@ -35,20 +40,24 @@ pub fn is_active(player: &Player) -> bool {
 }
 
 /// Determine if the MPRIS identity or the unique part of the D-Bus bus name
-/// (i.e. the part after `org.mpris.MediaPlayer2.`) is contained in the regex set.
+/// (i.e. the part after `org.mpris.MediaPlayer2.`) matches a pattern.
 ///
 /// This takes into account the possibility of multiple player instances:
 /// it checks both the name, and the name with the instance part
 /// (something like `.instance123`) stripped off.
-fn regex_set_contains(set: &RegexSet, player: &Player) -> bool {
-    let bus_name = player.bus_name().trim_start_matches(BUS_NAME_PREFIX);
+fn player_matches(player: &Player, is_match: impl Fn(&str) -> bool) -> bool {
+    let bus_name = short_name(player.bus_name());
 
     let without_instance = bus_name
         .rsplit_once('.')
         .map(|(name, _instance)| name)
         .unwrap_or(bus_name);
 
-    set.is_match(player.identity()) || set.is_match(bus_name) || set.is_match(without_instance)
+    is_match(player.identity()) || is_match(bus_name) || is_match(without_instance)
+}
+
+fn regex_set_contains(set: &RegexSet, player: &Player) -> bool {
+    player_matches(player, |name| set.is_match(name))
 }
 
 /// Determine if a player's MPRIS identity or its D-Bus bus name are whitelisted.
@@ -71,9 +80,56 @@ fn is_ignorelisted(config: &Config, player: &Player) -> bool {
     false
 }
 
+/// Determine if a player is whitelisted and not ignorelisted.
+pub fn is_allowed(config: &Config, player: &Player) -> bool {
+    is_whitelisted(config, player) && !is_ignorelisted(config, player)
+}
+
+/// Get the first `[[player]]` definition that matches a player.
+fn definition<'c>(config: &'c Config, player: &Player) -> Option<&'c PlayerConfig> {
+    config
+        .player
+        .iter()
+        .flatten()
+        .find(|definition| player_matches(player, |name| definition.name.is_match(name)))
+}
+
+/// Get the scrobble thresholds for a player: those of the first `[[player]]`
+/// definition that matches it, falling back to the top-level options.
+pub fn thresholds(config: &Config, player: &Player) -> Thresholds {
+    definition(config, player)
+        .map(|definition| definition.thresholds.or(&config.thresholds))
+        .unwrap_or_else(|| config.thresholds.clone())
+}
+
+/// Determine if a player is configured to play internet streams (or not)
+/// in its `[[player]]` definition.
+pub fn is_stream_player(config: &Config, player: &Player) -> Option<bool> {
+    definition(config, player).and_then(|definition| definition.stream)
+}
+
+/// Determine if a player's tracks are normalized: if its `[[player]]` definition
+/// sets `normalize`, that is used, otherwise it depends on the `[normalize]` section.
+pub fn is_normalized(config: &Config, player: &Player) -> bool {
+    definition(config, player)
+        .and_then(|definition| definition.normalize)
+        .unwrap_or(config.normalize.is_some())
+}
+
 /// Wait for any (whitelisted, not ignorelisted) player to become active again.
-pub fn wait_for_player(config: &Config, finder: &PlayerFinder) -> Player {
+///
+/// The `keep_waiting` callback is polled between attempts; if it returns `false`,
+/// waiting is aborted and `None` is returned.
+pub fn wait_for_player(
+    config: &Config,
+    finder: &PlayerFinder,
+    mut keep_waiting: impl FnMut() -> bool,
+) -> Option<Player> {
     loop {
+        if !keep_waiting() {
+            return None;
+        }
+
         let players = match finder.iter_players() {
             Ok(players) => players,
             _ => {
@@ -85,10 +141,9 @@ pub fn wait_for_player(config: &Config, finder: &PlayerFinder) -> Player {
         for player in players {
             if let Ok(player) = player
                 && is_active(&player)
-                && is_whitelisted(config, &player)
-                && !is_ignorelisted(config, &player)
+                && is_allowed(config, &player)
             {
-                return player;
+                return Some(player);
             }
         }
 
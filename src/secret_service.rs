@@ -0,0 +1,522 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Client for the freedesktop Secret Service API (`org.freedesktop.secrets`),
+//! implemented by e.g. GNOME Keyring and KWallet.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::{Connection, Proxy};
+use dbus::message::MatchRule;
+
+use serde::{Deserialize, Serialize};
+
+const BUS_NAME: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const PROMPT_INTERFACE: &str = "org.freedesktop.Secret.Prompt";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the user to respond to an unlock prompt.
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Attribute identifying items created by rescrobbled.
+const APPLICATION: (&str, &str) = ("application", "rescrobbled");
+/// Type attribute of the secrets used by the `*-secret-service` config options.
+const CONFIG_SECRET_TYPE: &str = "config-secret";
+
+/// A secret as transferred over D-Bus: session, parameters, value and content type.
+type SecretStruct = (dbus::Path<'static>, Vec<u8>, Vec<u8>, String);
+
+/// Where Last.fm sessions are stored.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionStore {
+    /// In files in the config directory.
+    #[default]
+    File,
+    /// In the Secret Service, falling back to files if it is not available.
+    SecretService,
+}
+
+pub struct SecretService {
+    connection: Connection,
+    session: dbus::Path<'static>,
+}
+
+impl SecretService {
+    /// Connect to the Secret Service on the session bus.
+    pub fn connect() -> Result<Self> {
+        let connection = Connection::new_session().context("Failed to connect to D-Bus")?;
+        Self::with_connection(connection)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        // Secrets are transferred unencrypted; the session bus is only accessible by the user
+        let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) =
+            Proxy::new(BUS_NAME, SERVICE_PATH, TIMEOUT, &connection)
+                .method_call(
+                    SERVICE_INTERFACE,
+                    "OpenSession",
+                    ("plain", Variant(Box::new(String::new()) as Box<dyn RefArg>)),
+                )
+                .context("Failed to open a Secret Service session")?;
+
+        Ok(Self {
+            connection,
+            session,
+        })
+    }
+
+    fn proxy<'a>(&'a self, path: &'a dbus::Path<'static>) -> Proxy<'a, &'a Connection> {
+        Proxy::new(BUS_NAME, path.clone(), TIMEOUT, &self.connection)
+    }
+
+    fn service(&self) -> Proxy<'_, &Connection> {
+        Proxy::new(BUS_NAME, SERVICE_PATH, TIMEOUT, &self.connection)
+    }
+
+    fn attributes(attributes: &[(&str, &str)]) -> HashMap<String, String> {
+        [APPLICATION]
+            .iter()
+            .chain(attributes)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Let the user respond to a prompt, e.g. to unlock the keyring, and wait until it completes.
+    fn prompt(&self, prompt: dbus::Path<'static>) -> Result<()> {
+        if &*prompt == "/" {
+            return Ok(());
+        }
+
+        let completed = Arc::new(Mutex::new(None));
+
+        let rule = MatchRule::new_signal(PROMPT_INTERFACE, "Completed").with_path(prompt.clone());
+        let token = self.connection.add_match(rule, {
+            let completed = Arc::clone(&completed);
+            move |(dismissed,): (bool,), _, _| {
+                *completed.lock().unwrap() = Some(dismissed);
+                false
+            }
+        })?;
+
+        self.proxy(&prompt)
+            .method_call::<(), _, _, _>(PROMPT_INTERFACE, "Prompt", ("",))
+            .context("Failed to show Secret Service prompt")?;
+
+        let started = Instant::now();
+        while completed.lock().unwrap().is_none() && started.elapsed() < PROMPT_TIMEOUT {
+            self.connection.process(Duration::from_millis(500))?;
+        }
+
+        let _ = self.connection.remove_match(token);
+
+        let completed = *completed.lock().unwrap();
+        match completed {
+            Some(false) => Ok(()),
+            Some(true) => bail!("The Secret Service prompt was dismissed"),
+            None => bail!("Timed out waiting for the Secret Service prompt"),
+        }
+    }
+
+    /// Unlock the given objects (items or collections), prompting the user if necessary.
+    fn unlock(&self, objects: Vec<dbus::Path<'static>>) -> Result<()> {
+        if objects.is_empty() {
+            return Ok(());
+        }
+
+        let (_, prompt): (Vec<dbus::Path<'static>>, dbus::Path<'static>) = self
+            .service()
+            .method_call(SERVICE_INTERFACE, "Unlock", (objects,))
+            .context("Failed to unlock the Secret Service")?;
+
+        self.prompt(prompt)
+    }
+
+    /// Find all secrets stored by rescrobbled with the given attributes.
+    pub fn search(&self, attributes: &[(&str, &str)]) -> Result<Vec<String>> {
+        let (mut unlocked, locked): (Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>) = self
+            .service()
+            .method_call(
+                SERVICE_INTERFACE,
+                "SearchItems",
+                (Self::attributes(attributes),),
+            )
+            .context("Failed to search the Secret Service")?;
+
+        if !locked.is_empty() {
+            self.unlock(locked.clone())?;
+            unlocked.extend(locked);
+        }
+
+        if unlocked.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (secrets,): (HashMap<dbus::Path<'static>, SecretStruct>,) = self
+            .service()
+            .method_call(
+                SERVICE_INTERFACE,
+                "GetSecrets",
+                (unlocked, self.session.clone()),
+            )
+            .context("Failed to get secrets from the Secret Service")?;
+
+        secrets
+            .into_values()
+            .map(|(_, _, value, _)| {
+                String::from_utf8(value)
+                    .context("Secret from the Secret Service is not valid UTF-8")
+            })
+            .collect()
+    }
+
+    /// Store a secret in the default collection, replacing an existing secret with the same attributes.
+    pub fn store(&self, label: &str, attributes: &[(&str, &str)], secret: &str) -> Result<()> {
+        let (collection,): (dbus::Path<'static>,) = self
+            .service()
+            .method_call(SERVICE_INTERFACE, "ReadAlias", ("default",))
+            .context("Failed to find the default Secret Service collection")?;
+
+        if &*collection == "/" {
+            bail!("The Secret Service has no default collection");
+        }
+
+        self.unlock(vec![collection.clone()])?;
+
+        let mut properties = PropMap::new();
+        properties.insert(
+            "org.freedesktop.Secret.Item.Label".to_owned(),
+            Variant(Box::new(label.to_owned())),
+        );
+        properties.insert(
+            "org.freedesktop.Secret.Item.Attributes".to_owned(),
+            Variant(Box::new(Self::attributes(attributes))),
+        );
+
+        let secret: SecretStruct = (
+            self.session.clone(),
+            Vec::new(),
+            secret.as_bytes().to_vec(),
+            "text/plain".to_owned(),
+        );
+
+        let (_, prompt): (dbus::Path<'static>, dbus::Path<'static>) = self
+            .proxy(&collection)
+            .method_call(
+                COLLECTION_INTERFACE,
+                "CreateItem",
+                (properties, secret, true),
+            )
+            .context("Failed to store secret in the Secret Service")?;
+
+        self.prompt(prompt)
+    }
+
+    /// Get the secret stored for the `*-secret-service` config options under `name`.
+    pub fn lookup(&self, name: &str) -> Result<String> {
+        self.search(&[("type", CONFIG_SECRET_TYPE), ("name", name)])?
+            .pop()
+            .ok_or_else(|| {
+                anyhow!(
+                    "No secret named `{name}` in the Secret Service, \
+                    store it using `rescrobbled auth secret-service {name}`"
+                )
+            })
+    }
+
+    /// Store a secret for the `*-secret-service` config options under `name`.
+    pub fn store_config_secret(&self, name: &str, secret: &str) -> Result<()> {
+        self.store(
+            &format!("rescrobbled secret ({name})"),
+            &[("type", CONFIG_SECRET_TYPE), ("name", name)],
+            secret,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+
+    use dbus::channel::Channel;
+    use dbus_crossroads::{Context, Crossroads};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Item {
+        attributes: HashMap<String, String>,
+        secret: Vec<u8>,
+        locked: bool,
+    }
+
+    type Items = Arc<Mutex<Vec<Item>>>;
+
+    fn item_path(index: usize) -> dbus::Path<'static> {
+        format!("/org/freedesktop/secrets/collection/login/{index}").into()
+    }
+
+    fn item_index(path: &dbus::Path) -> usize {
+        path.rsplit('/').next().unwrap().parse().unwrap()
+    }
+
+    /// A private message bus, which is stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon should be installed to run this test");
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.as_mut().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+
+            Self {
+                daemon,
+                address: address.trim().to_owned(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            let mut channel = Channel::open_private(&self.address).unwrap();
+            channel.register().unwrap();
+            Connection::from(channel)
+        }
+    }
+
+    /// Run a minimal Secret Service implementation on the bus.
+    fn serve_mock(bus: &Bus, items: Items) {
+        let connection = bus.connect();
+        connection
+            .request_name(BUS_NAME, false, true, false)
+            .unwrap();
+
+        thread::spawn(move || {
+            let mut cr = Crossroads::new();
+
+            let service = cr.register(SERVICE_INTERFACE, |b| {
+                b.method(
+                    "OpenSession",
+                    ("algorithm", "input"),
+                    ("output", "result"),
+                    |_, _: &mut Items, (algorithm, _): (String, Variant<Box<dyn RefArg>>)| {
+                        assert_eq!(algorithm, "plain");
+                        let output = Variant(Box::new(String::new()) as Box<dyn RefArg>);
+                        Ok((output, dbus::Path::from("/org/freedesktop/secrets/session/1")))
+                    },
+                );
+                b.method(
+                    "SearchItems",
+                    ("attributes",),
+                    ("unlocked", "locked"),
+                    |_, items: &mut Items, (attributes,): (HashMap<String, String>,)| {
+                        let (mut unlocked, mut locked) = (Vec::new(), Vec::new());
+                        for (i, item) in items.lock().unwrap().iter().enumerate() {
+                            if attributes
+                                .iter()
+                                .all(|(name, value)| item.attributes.get(name) == Some(value))
+                            {
+                                match item.locked {
+                                    true => locked.push(item_path(i)),
+                                    false => unlocked.push(item_path(i)),
+                                }
+                            }
+                        }
+                        Ok((unlocked, locked))
+                    },
+                );
+                b.method(
+                    "Unlock",
+                    ("objects",),
+                    ("unlocked", "prompt"),
+                    |_, items: &mut Items, (objects,): (Vec<dbus::Path<'static>>,)| {
+                        let mut items = items.lock().unwrap();
+                        for path in &objects {
+                            if let Some(item) = path
+                                .rsplit('/')
+                                .next()
+                                .and_then(|i| i.parse::<usize>().ok())
+                                .and_then(|i| items.get_mut(i))
+                            {
+                                item.locked = false;
+                            }
+                        }
+                        Ok((objects, dbus::Path::from("/")))
+                    },
+                );
+                b.method(
+                    "GetSecrets",
+                    ("items", "session"),
+                    ("secrets",),
+                    |_,
+                     items: &mut Items,
+                     (paths, session): (Vec<dbus::Path<'static>>, dbus::Path<'static>)| {
+                        let items = items.lock().unwrap();
+                        let secrets: HashMap<_, SecretStruct> = paths
+                            .into_iter()
+                            .map(|path| {
+                                let secret = items[item_index(&path)].secret.clone();
+                                let value = (session.clone(), Vec::new(), secret, "text/plain".into());
+                                (path, value)
+                            })
+                            .collect();
+                        Ok((secrets,))
+                    },
+                );
+                b.method(
+                    "ReadAlias",
+                    ("name",),
+                    ("collection",),
+                    |_, _: &mut Items, (name,): (String,)| {
+                        assert_eq!(name, "default");
+                        Ok((dbus::Path::from("/org/freedesktop/secrets/collection/login"),))
+                    },
+                );
+            });
+
+            let collection = cr.register(COLLECTION_INTERFACE, |b| {
+                b.method(
+                    "CreateItem",
+                    ("properties", "secret", "replace"),
+                    ("item", "prompt"),
+                    |_: &mut Context,
+                     items: &mut Items,
+                     (properties, secret, replace): (PropMap, SecretStruct, bool)| {
+                        let mut attributes = HashMap::new();
+                        let value = &properties["org.freedesktop.Secret.Item.Attributes"].0;
+                        let mut iter = value.as_iter().unwrap();
+                        while let (Some(name), Some(value)) = (iter.next(), iter.next()) {
+                            attributes.insert(
+                                name.as_str().unwrap().to_owned(),
+                                value.as_str().unwrap().to_owned(),
+                            );
+                        }
+
+                        let mut items = items.lock().unwrap();
+                        let item = Item {
+                            attributes,
+                            secret: secret.2,
+                            locked: false,
+                        };
+
+                        let index = match items.iter().position(|i| i.attributes == item.attributes) {
+                            Some(index) if replace => {
+                                items[index] = item;
+                                index
+                            }
+                            _ => {
+                                items.push(item);
+                                items.len() - 1
+                            }
+                        };
+
+                        Ok((item_path(index), dbus::Path::from("/")))
+                    },
+                );
+            });
+
+            cr.insert(SERVICE_PATH, &[service], items.clone());
+            cr.insert(
+                "/org/freedesktop/secrets/collection/login",
+                &[collection],
+                items,
+            );
+
+            let _ = cr.serve(&connection);
+        });
+    }
+
+    #[test]
+    #[ignore = "requires dbus-daemon, run with `cargo test -- --ignored`"]
+    fn test_secret_service() {
+        let bus = Bus::start();
+
+        let items = Items::default();
+        serve_mock(&bus, Arc::clone(&items));
+
+        let secret_service = SecretService::with_connection(bus.connect()).unwrap();
+
+        let attributes = [("type", "lastfm-session"), ("username", "alice")];
+
+        assert!(secret_service.search(&attributes).unwrap().is_empty());
+
+        secret_service
+            .store("Last.fm session", &attributes, "abc")
+            .unwrap();
+        secret_service
+            .store("Last.fm session", &attributes, "def")
+            .unwrap();
+
+        assert_eq!(secret_service.search(&attributes).unwrap(), vec!["def"]);
+        assert_eq!(
+            items.lock().unwrap()[0].attributes["application"],
+            "rescrobbled"
+        );
+
+        // Locked items are unlocked
+        items.lock().unwrap()[0].locked = true;
+        assert_eq!(
+            secret_service
+                .search(&[("type", "lastfm-session")])
+                .unwrap(),
+            vec!["def"]
+        );
+        assert!(!items.lock().unwrap()[0].locked);
+
+        assert!(
+            secret_service
+                .search(&[("username", "bob")])
+                .unwrap()
+                .is_empty()
+        );
+
+        // Secrets for the config options
+        assert!(secret_service.lookup("lastfm-key").is_err());
+        secret_service
+            .store_config_secret("lastfm-key", "0123456789abcdef")
+            .unwrap();
+        assert_eq!(
+            secret_service.lookup("lastfm-key").unwrap(),
+            "0123456789abcdef"
+        );
+    }
+}
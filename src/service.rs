@@ -14,23 +14,48 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::fmt::{self, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 
 use listenbrainz::ListenBrainz;
+use listenbrainz::raw::Client;
+use listenbrainz::raw::request::{ListenType, Payload, SubmitListens, TrackMetadata};
 
-use rustfm_scrobble_proxy::{Scrobble, Scrobbler};
+use log::{debug, error, info, warn};
 
 mod lastfm;
+pub mod retry;
 
 use crate::config::secrets::Secret;
-use crate::config::{Config, ListenBrainzConfig};
+use crate::config::{Config, LastFmConfig, ListenBrainzConfig};
+use crate::secret_service::SessionStore;
 use crate::track::Track;
 
+/// The maximum number of scrobbles in a Last.fm batch.
+const LASTFM_BATCH_SIZE: usize = 50;
+/// The maximum number of listens in a ListenBrainz request.
+const LISTENBRAINZ_BATCH_SIZE: usize = 1000;
+
+/// Last.fm does not accept scrobbles that are older than 14 days.
+const LASTFM_MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// A scrobble that was accepted by a service.
+#[derive(Debug, Default, PartialEq)]
+pub struct Accepted {
+    /// The track as the service recorded it, if it corrected the artist, title or album.
+    pub corrected: Option<Track>,
+}
+
 /// Represents a music scrobbling service.
 pub enum Service {
-    LastFM(Scrobbler),
+    LastFM {
+        client: lastfm::Client,
+        /// The configured account, if any.
+        username: Option<String>,
+        key: String,
+        secret: String,
+    },
     ListenBrainz {
         client: ListenBrainz,
         is_default: bool,
@@ -38,20 +63,67 @@ pub enum Service {
 }
 
 impl Service {
-    /// Try to connect to Last.fm.
-    fn lastfm(config: &Config) -> Result<Option<Self>> {
-        match (&config.lastfm_key, &config.lastfm_secret) {
-            (Some(key), Some(secret)) => {
-                let mut scrobbler = Scrobbler::new(&key.get()?, &secret.get()?);
+    /// Try to connect to a Last.fm account.
+    fn lastfm(lf: &LastFmConfig, store: SessionStore) -> Result<Self> {
+        let (key, secret) = (lf.key.get()?, lf.secret.get()?);
 
-                lastfm::authenticate(&mut scrobbler)
-                    .context("Failed to authenticate with Last.fm")?;
+        let client = lastfm::authenticate(&key, &secret, lf.username.as_deref(), store)
+            .with_context(|| {
+                let mut err = "Failed to authenticate with Last.fm".to_owned();
+                if let Some(ref username) = lf.username {
+                    write!(err, " ({username})").unwrap();
+                }
+                err
+            })?;
 
-                Ok(Some(Self::LastFM(scrobbler)))
-            }
-            (None, None) => Ok(None),
-            _ => Err(anyhow!("Last.fm API key or API secret are missing")),
-        }
+        Ok(Self::LastFM {
+            client,
+            username: lf.username.clone(),
+            key: key.into_owned(),
+            secret: secret.into_owned(),
+        })
+    }
+
+    /// Log in to Last.fm by authorizing rescrobbled in a browser, replacing the stored session.
+    /// If multiple accounts are configured, `username` selects the account.
+    pub fn login_lastfm(config: &Config, username: Option<&str>) -> Result<()> {
+        let account = Self::lastfm_account(config, username)?;
+
+        lastfm::login(
+            &account.key.get()?,
+            &account.secret.get()?,
+            account.username.as_deref().or(username),
+            config.session_store.unwrap_or_default(),
+        )
+        .context("Failed to authenticate with Last.fm")
+    }
+
+    /// Find the Last.fm account to log in to. A single account without a username
+    /// can be used for any username.
+    fn lastfm_account<'a>(config: &'a Config, username: Option<&str>) -> Result<&'a LastFmConfig> {
+        let accounts: Vec<_> = config.lastfm.iter().flatten().collect();
+
+        let account = match (username, &accounts[..]) {
+            (_, []) => bail!("Last.fm API key or API secret are missing"),
+            (None, [account]) => account,
+            (None, _) => bail!(
+                "Multiple Last.fm accounts are configured, \
+                use `rescrobbled auth lastfm <username>` to choose one"
+            ),
+            (Some(username), _) => accounts
+                .iter()
+                .find(|account| account.username.as_deref() == Some(username))
+                .or_else(|| {
+                    accounts
+                        .first()
+                        .filter(|account| accounts.len() == 1 && account.username.is_none())
+                })
+                .ok_or_else(|| {
+                    anyhow!("No Last.fm account with username {username} is configured")
+                })?,
+        };
+
+        Ok(account)
     }
 
     /// Try to connect to a ListenBrainz instance.
@@ -75,31 +147,115 @@ impl Service {
         })
     }
 
+    /// Initialize a Last.fm service and add it to `services`.
+    fn initialize_lastfm(lf: &LastFmConfig, store: SessionStore, services: &mut Vec<Self>) {
+        match Self::lastfm(lf, store) {
+            Ok(service) => {
+                info!("Authenticated with {} successfully!", service);
+                services.push(service);
+            }
+            Err(err) => error!("{:#}", err),
+        }
+    }
+
+    /// Initialize a ListenBrainz service and add it to `services`.
+    fn initialize_listenbrainz(lb: &ListenBrainzConfig, services: &mut Vec<Self>) {
+        match Self::listenbrainz(lb) {
+            Ok(service) => {
+                info!("Authenticated with {} successfully!", service);
+                services.push(service);
+            }
+            Err(err) => error!("{:#}", err),
+        }
+    }
+
     /// Initialize all services specified in the config.
     pub fn initialize_all(config: &Config) -> Vec<Self> {
         let mut services = Vec::new();
 
-        match Self::lastfm(config) {
-            Ok(Some(lastfm)) => {
-                println!("Authenticated with {} successfully!", lastfm);
-                services.push(lastfm);
-            }
-            Err(err) => eprintln!("{:?}", err),
-            _ => {}
+        for lf in config.lastfm.iter().flatten() {
+            Self::initialize_lastfm(lf, config.session_store.unwrap_or_default(), &mut services);
         }
 
         for lb in config.listenbrainz.iter().flatten() {
-            match Self::listenbrainz(lb) {
-                Ok(service) => {
-                    println!("Authenticated with {} successfully!", service);
-                    services.push(service);
+            Self::initialize_listenbrainz(lb, &mut services);
+        }
+
+        if services.is_empty() {
+            warn!("No scrobbling services defined");
+        }
+
+        services
+    }
+
+    /// Determine if this is a Last.fm service that was created from the given config.
+    fn is_lastfm(&self, lf: &LastFmConfig) -> bool {
+        let Self::LastFM {
+            username,
+            key,
+            secret,
+            ..
+        } = self
+        else {
+            return false;
+        };
+
+        *username == lf.username
+            && lf.key.get().ok().as_deref() == Some(key)
+            && lf.secret.get().ok().as_deref() == Some(secret)
+    }
+
+    /// Determine if this is a ListenBrainz service that was created from the given config.
+    fn is_listenbrainz(&self, lb: &ListenBrainzConfig) -> bool {
+        let Self::ListenBrainz { client, is_default } = self else {
+            return false;
+        };
+
+        let same_url = match lb.url {
+            Some(ref url) => !is_default && client.api_url() == url,
+            None => *is_default,
+        };
+
+        same_url && lb.token.get().ok().as_deref() == client.authenticated_token()
+    }
+
+    /// Re-initialize services after the config was reloaded.
+    ///
+    /// Services whose settings did not change are kept as-is,
+    /// so they do not have to authenticate again.
+    pub fn reinitialize(services: Vec<Self>, new: &Config) -> Vec<Self> {
+        let mut previous = services;
+        let mut services = Vec::new();
+
+        for lf in new.lastfm.iter().flatten() {
+            match previous.iter().position(|service| service.is_lastfm(lf)) {
+                Some(index) => {
+                    debug!("Settings for {} did not change", previous[index]);
+                    services.push(previous.swap_remove(index));
+                }
+                None => Self::initialize_lastfm(
+                    lf,
+                    new.session_store.unwrap_or_default(),
+                    &mut services,
+                ),
+            }
+        }
+
+        for lb in new.listenbrainz.iter().flatten() {
+            match previous
+                .iter()
+                .position(|service| service.is_listenbrainz(lb))
+            {
+                Some(index) => {
+                    debug!("Settings for {} did not change", previous[index]);
+                    services.push(previous.swap_remove(index));
                 }
-                Err(err) => eprintln!("{:?}", err),
+                None => Self::initialize_listenbrainz(lb, &mut services),
             }
         }
 
         if services.is_empty() {
-            eprintln!("Warning: no scrobbling services defined");
+            warn!("No scrobbling services defined");
         }
 
         services
@@ -108,11 +264,9 @@ impl Service {
     /// Submit a "now playing" request.
     pub fn now_playing(&self, track: &Track) -> Result<()> {
         match self {
-            Self::LastFM(scrobbler) => {
-                let scrobble = Scrobble::new(track.artist(), track.title(), track.album());
-
-                scrobbler
-                    .now_playing(&scrobble)
+            Self::LastFM { client, .. } => {
+                client
+                    .now_playing(track)
                     .with_context(|| format!("Failed to update status on {}", self))?;
             }
             Self::ListenBrainz { client, .. } => {
@@ -124,38 +278,175 @@ impl Service {
         Ok(())
     }
 
-    /// Scrobble a track.
-    pub fn submit(&self, track: &Track, track_start: Option<&SystemTime>) -> Result<()> {
+    /// Scrobble a track with the time it was played. Fails if the service ignored the scrobble.
+    pub fn submit(&self, track: &Track, timestamp: &SystemTime) -> Result<Accepted> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .context("Track started before UNIX epoch")?
+            .as_secs();
+
+        match self {
+            Self::LastFM { client, .. } => {
+                let result = client
+                    .scrobble(&[(track, timestamp)])
+                    .with_context(|| format!("Failed to submit track to {}", self))?
+                    .pop()
+                    .expect("one result per scrobble");
+
+                return result
+                    .map(|corrected| Accepted { corrected })
+                    .with_context(|| format!("Failed to submit track to {}", self));
+            }
+            Self::ListenBrainz { client, .. } => {
+                self.submit_listens(client, ListenType::Single, &[(track, timestamp)])
+                    .with_context(|| format!("Failed to submit track to {}", self))?;
+            }
+        }
+        Ok(Accepted::default())
+    }
+
+    /// A stable identifier of the account, which the submissions in the history are
+    /// stored under. Unlike the name, it does not change when e.g. a username is added
+    /// to the config or a ListenBrainz URL is written differently.
+    pub fn id(&self) -> String {
         match self {
-            Self::LastFM(scrobbler) => {
-                let mut scrobble = Scrobble::new(track.artist(), track.title(), track.album());
+            Self::LastFM { client, key, .. } => match client.username() {
+                Some(username) => format!("lastfm:{key}:{}", username.to_lowercase()),
+                None => format!("lastfm:{key}"),
+            },
+            Self::ListenBrainz { client, .. } => format!(
+                "listenbrainz:{}:{}",
+                client.api_url().trim_end_matches('/'),
+                client.authenticated_user().unwrap_or_default()
+            ),
+        }
+    }
 
-                if let Some(track_start) = track_start {
-                    let timestamp = track_start
-                        .duration_since(UNIX_EPOCH)
-                        .context("Track started before UNIX epoch")?;
+    /// Explain how to fix an authentication failure.
+    pub fn login_hint(&self) -> &'static str {
+        match self {
+            Self::LastFM { .. } => "run `rescrobbled auth lastfm` to log in again",
+            Self::ListenBrainz { .. } => "check the ListenBrainz token in the config",
+        }
+    }
 
-                    scrobble.with_timestamp(timestamp.as_secs());
-                }
+    /// The maximum number of scrobbles that can be submitted in one batch.
+    pub fn batch_size(&self) -> usize {
+        match self {
+            Self::LastFM { .. } => LASTFM_BATCH_SIZE,
+            Self::ListenBrainz { .. } => LISTENBRAINZ_BATCH_SIZE,
+        }
+    }
 
-                scrobbler
-                    .scrobble(&scrobble)
-                    .with_context(|| format!("Failed to submit track to {}", self))?;
+    /// How old a scrobble can be to still be accepted, if there is a limit.
+    pub fn max_age(&self) -> Option<Duration> {
+        match self {
+            Self::LastFM { .. } => Some(LASTFM_MAX_AGE),
+            Self::ListenBrainz { .. } => None,
+        }
+    }
+
+    /// Scrobble multiple tracks in one request. Each track is given with the time it
+    /// started playing, in seconds since the UNIX epoch.
+    /// At most `batch_size` tracks can be submitted at once.
+    ///
+    /// If the request succeeds, returns the result of each scrobble,
+    /// which fails if the service ignored it.
+    pub fn submit_batch(&self, scrobbles: &[(&Track, u64)]) -> Result<Vec<Result<Accepted>>> {
+        match self {
+            Self::LastFM { client, .. } => {
+                let results = client
+                    .scrobble(scrobbles)
+                    .with_context(|| format!("Failed to submit tracks to {}", self))?
+                    .into_iter()
+                    .zip(scrobbles)
+                    .map(|(result, (track, _))| {
+                        result
+                            .map(|corrected| Accepted { corrected })
+                            .with_context(|| {
+                                format!(
+                                    "Failed to submit {} - {} to {}",
+                                    track.artist(),
+                                    track.title(),
+                                    self
+                                )
+                            })
+                    })
+                    .collect();
+
+                return Ok(results);
             }
             Self::ListenBrainz { client, .. } => {
-                client
-                    .listen(track.artist(), track.title(), track.album())
-                    .with_context(|| format!("Failed to submit track to {}", self))?;
+                self.submit_listens(client, ListenType::Import, scrobbles)
+                    .with_context(|| format!("Failed to submit tracks to {}", self))?;
             }
         }
+        Ok(scrobbles.iter().map(|_| Ok(Accepted::default())).collect())
+    }
+
+    /// Submit listens with their timestamps to ListenBrainz.
+    /// The high-level client can only submit a single listen at the current time, or import one.
+    fn submit_listens(
+        &self,
+        client: &ListenBrainz,
+        listen_type: ListenType,
+        scrobbles: &[(&Track, u64)],
+    ) -> Result<()> {
+        let token = client
+            .authenticated_token()
+            .ok_or_else(|| anyhow!("Not authenticated with {}", self))?;
+
+        let payload: Vec<Payload<&str>> = scrobbles
+            .iter()
+            .map(|(track, timestamp)| Payload {
+                listened_at: Some(*timestamp as i64),
+                track_metadata: TrackMetadata {
+                    track_name: track.title(),
+                    artist_name: track.artist(),
+                    release_name: track.album(),
+                    additional_info: None,
+                },
+            })
+            .collect();
+
+        Client::new_with_url(client.api_url()).submit_listens(
+            token,
+            SubmitListens {
+                listen_type,
+                payload: &payload,
+            },
+        )?;
+
         Ok(())
     }
 }
 
+#[cfg(test)]
+pub use lastfm::{scrobble_response, serve};
+
+#[cfg(test)]
+impl Service {
+    /// A Last.fm service that uses a mock API at `url`.
+    pub fn mock_lastfm(url: &str) -> Self {
+        Self::LastFM {
+            client: lastfm::Client::with_url(url),
+            username: None,
+            key: "key".to_owned(),
+            secret: "secret".to_owned(),
+        }
+    }
+}
+
 impl fmt::Display for Service {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::LastFM(_) => write!(f, "Last.fm"),
+            Self::LastFM { username, .. } => {
+                write!(f, "Last.fm")?;
+                if let Some(username) = username {
+                    write!(f, " ({username})")?;
+                }
+                Ok(())
+            }
             Self::ListenBrainz { client, is_default } => {
                 write!(f, "ListenBrainz")?;
                 if !is_default {
@@ -166,3 +457,79 @@ impl fmt::Display for Service {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lastfm_account() {
+        let account = |username: Option<&str>| LastFmConfig {
+            username: username.map(str::to_owned),
+            ..Default::default()
+        };
+        let username = |config: &Config, name| {
+            Service::lastfm_account(config, name)
+                .map(|account| account.username.clone())
+                .map_err(|err| err.to_string())
+        };
+
+        let config = Config {
+            lastfm: Some(vec![account(None)]),
+            ..Default::default()
+        };
+        assert_eq!(username(&config, None), Ok(None));
+        assert_eq!(username(&config, Some("alice")), Ok(None));
+
+        let config = Config {
+            lastfm: Some(vec![account(Some("alice"))]),
+            ..Default::default()
+        };
+        assert_eq!(username(&config, None), Ok(Some("alice".to_owned())));
+        assert_eq!(
+            username(&config, Some("bob")),
+            Err("No Last.fm account with username bob is configured".to_owned())
+        );
+
+        let config = Config {
+            lastfm: Some(vec![account(Some("alice")), account(Some("bob"))]),
+            ..Default::default()
+        };
+        assert_eq!(username(&config, Some("bob")), Ok(Some("bob".to_owned())));
+        assert!(username(&config, None).is_err());
+    }
+
+    #[test]
+    fn test_submit_batch() {
+        let (url, server) = serve(&[(200, &scrobble_response(&[None, Some((3, ""))]))]);
+        let service = Service::mock_lastfm(&url);
+
+        let first = Track::new("Boards of Canada", "Roygbiv", None);
+        let second = Track::new("Boards of Canada", "Olson", None);
+        let results = service
+            .submit_batch(&[(&first, 1790812800), (&second, 1790812900)])
+            .unwrap();
+
+        // Both scrobbles are submitted in one request
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("track%5B0%5D=Roygbiv"));
+        assert!(requests[0].contains("track%5B1%5D=Olson"));
+        assert!(requests[0].contains("timestamp%5B1%5D=1790812900"));
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "Failed to submit Boards of Canada - Olson to Last.fm"
+        );
+        assert_eq!(
+            results[1]
+                .as_ref()
+                .unwrap_err()
+                .downcast_ref::<lastfm::IgnoredError>()
+                .map(|err| err.code),
+            Some(3)
+        );
+    }
+}
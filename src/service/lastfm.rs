@@ -12,54 +12,514 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
-use std::fs::{self, Permissions};
+use std::fs::{self, DirBuilder, OpenOptions};
 use std::io::{self, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 
-use rustfm_scrobble_proxy::Scrobbler;
-
-use rpassword::read_password;
+use log::{debug, info, warn};
 
 use crate::config::config_dir;
+use crate::secret_service::{SecretService, SessionStore};
+use crate::track::Track;
+
+mod api;
+
+use api::{Api, codes};
+pub use api::{ApiError, IgnoredError};
 
-const SESSION_FILE: &str = "session";
+/// Session file used by older versions, shared by all accounts.
+const LEGACY_SESSION_FILE: &str = "session";
+/// Directory containing a session file per API key and account.
+const SESSION_DIR: &str = "sessions/lastfm";
+/// Type attribute of sessions stored in the Secret Service.
+const SECRET_TYPE: &str = "lastfm-session";
 
-/// Authenticate with Last.fm either using an existing
-/// session file or by logging in.
-pub fn authenticate(scrobbler: &mut Scrobbler) -> Result<()> {
+/// How often to check if the user has authorized the token.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// Tokens are valid for 60 minutes.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// The directory containing the session files for an API key, named after the accounts.
+fn session_dir(key: &str) -> Result<PathBuf> {
     let mut path = config_dir()?;
-    path.push(SESSION_FILE);
+    path.push(SESSION_DIR);
+    path.push(key);
+    Ok(path)
+}
+
+/// The path of the session file for the given account in `dir`.
+fn session_path(dir: &Path, username: &str) -> Result<PathBuf> {
+    if username.is_empty() || username == "." || username == ".." || username.contains(['/', '\0'])
+    {
+        bail!("Invalid Last.fm username `{}`", username.escape_default());
+    }
+
+    Ok(dir.join(username))
+}
+
+/// Find the session file for the given account, or the only
+/// session file if no account is specified.
+fn find_session(dir: &Path, username: Option<&str>) -> Result<Option<PathBuf>> {
+    if let Some(username) = username {
+        let path = session_path(dir, username)?;
+        return Ok(path.is_file().then_some(path));
+    }
+
+    let mut sessions: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err).context("Failed to read Last.fm session directory"),
+    };
 
-    if let Ok(session_key) = fs::read_to_string(&path) {
-        // TODO: validate session
-        scrobbler.authenticate_with_session_key(&session_key);
-    } else {
-        let mut input = String::new();
+    if sessions.len() > 1 {
+        sessions.sort();
 
-        print!(
-            "Log in to Last.fm\n\
-            Username: "
+        let names: Vec<_> = sessions
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .collect();
+
+        bail!(
+            "Logged in to multiple Last.fm accounts ({}), set `lastfm-username` to choose one",
+            names.join(", ")
         );
-        io::stdout().flush()?;
+    }
+
+    Ok(sessions.pop())
+}
+
+fn write_session(dir: &Path, username: &str, session_key: &str) -> Result<PathBuf> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .context("Failed to create Last.fm session directory")?;
+
+    let path = session_path(dir, username)?;
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(session_key.as_bytes()))
+        .context("Failed to write session file")?;
+
+    Ok(path)
+}
+
+/// A session key that was found in the session storage.
+struct StoredSession {
+    key: String,
+    /// The account the session was stored for, if known.
+    username: Option<String>,
+    /// Whether the session was found in the legacy session file.
+    is_legacy: bool,
+}
+
+/// Where the sessions for an API key are stored.
+struct Sessions {
+    key: String,
+    dir: PathBuf,
+    legacy: PathBuf,
+    secret_service: Option<SecretService>,
+}
+
+impl Sessions {
+    fn new(key: &str, store: SessionStore) -> Result<Self> {
+        let mut legacy = config_dir()?;
+        legacy.push(LEGACY_SESSION_FILE);
+
+        let secret_service = match store {
+            SessionStore::File => None,
+            SessionStore::SecretService => match SecretService::connect() {
+                Ok(secret_service) => Some(secret_service),
+                Err(err) => {
+                    warn!(
+                        "{:#}",
+                        err.context("Secret Service is not available, using session files instead")
+                    );
+                    None
+                }
+            },
+        };
+
+        Ok(Self {
+            key: key.to_owned(),
+            dir: session_dir(key)?,
+            legacy,
+            secret_service,
+        })
+    }
+
+    fn attributes<'a>(&'a self, username: Option<&'a str>) -> Vec<(&'a str, &'a str)> {
+        let mut attributes = vec![("type", SECRET_TYPE), ("api-key", self.key.as_str())];
+        if let Some(username) = username {
+            attributes.push(("username", username));
+        }
+        attributes
+    }
+
+    /// Find the session key for an account.
+    fn find(&self, username: Option<&str>) -> Result<Option<StoredSession>> {
+        if let Some(ref secret_service) = self.secret_service {
+            match secret_service.search(&self.attributes(username)) {
+                Ok(sessions) if sessions.len() > 1 => bail!(
+                    "Logged in to multiple Last.fm accounts, set `lastfm-username` to choose one"
+                ),
+                Ok(mut sessions) => {
+                    if let Some(key) = sessions.pop() {
+                        return Ok(Some(StoredSession {
+                            key,
+                            username: username.map(str::to_owned),
+                            is_legacy: false,
+                        }));
+                    }
+                }
+                Err(err) => warn!(
+                    "{:#}",
+                    err.context("Failed to load Last.fm session from the Secret Service")
+                ),
+            }
+        }
+
+        let path = match find_session(&self.dir, username)? {
+            Some(path) => path,
+            None if self.legacy.is_file() => self.legacy.clone(),
+            None => return Ok(None),
+        };
 
-        io::stdin().read_line(&mut input)?;
-        input.pop();
-        let username = input.clone();
+        let key = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read session file {}", path.display()))?
+            .trim()
+            .to_owned();
 
-        input.clear();
+        // Session files are named after the account
+        let is_legacy = path == self.legacy;
+        let username = match is_legacy {
+            true => None,
+            false => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(str::to_owned),
+        };
 
-        print!("Password: ");
-        io::stdout().flush()?;
+        Ok(Some(StoredSession {
+            key,
+            username,
+            is_legacy,
+        }))
+    }
+
+    /// Store the session key for an account. Returns where it was stored.
+    fn save(&self, username: &str, session_key: &str) -> Result<String> {
+        if let Some(ref secret_service) = self.secret_service {
+            let label = format!("rescrobbled Last.fm session ({username})");
 
-        let password = read_password().context("Failed to read password")?;
+            match secret_service.store(&label, &self.attributes(Some(username)), session_key) {
+                Ok(()) => return Ok("the Secret Service".to_owned()),
+                Err(err) => warn!(
+                    "{:#}",
+                    err.context(
+                        "Failed to store Last.fm session in the Secret Service, \
+                        using a session file instead"
+                    )
+                ),
+            }
+        }
 
-        let session_response = scrobbler.authenticate_with_password(&username, &password)?;
+        let path = write_session(&self.dir, username, session_key)?;
 
-        let _ = fs::write(&path, session_response.key);
-        let _ = fs::set_permissions(&path, Permissions::from_mode(0o600));
+        Ok(path.display().to_string())
+    }
+}
+
+/// Load the session key for an account and check that it is still valid.
+/// A session from the legacy session file is moved to the per-account storage.
+///
+/// Returns the session key, and the name of the account if it could be validated
+/// or is known from where the session was stored.
+fn load_session(
+    api: &Api,
+    sessions: &Sessions,
+    username: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let Some(StoredSession {
+        key: session_key,
+        username: stored_username,
+        is_legacy,
+    }) = sessions.find(username)?
+    else {
+        let account = username
+            .map(|name| format!(" as {name}"))
+            .unwrap_or_default();
+        bail!(
+            "Not logged in to Last.fm{account}. Run `rescrobbled auth lastfm` to log in, \
+            then restart or reload rescrobbled"
+        );
+    };
+
+    let name = match api.get_user_name(&session_key) {
+        Ok(name) => {
+            debug!("Last.fm session is valid for user {name}");
+
+            // The legacy session file can belong to any account
+            if let Some(username) = username
+                && !name.eq_ignore_ascii_case(username)
+            {
+                bail!(
+                    "The Last.fm session belongs to {name}, not to {username}. \
+                    Run `rescrobbled auth lastfm {username}` to log in as {username}"
+                );
+            }
+
+            if is_legacy {
+                let location = sessions.save(&name, &session_key)?;
+                fs::remove_file(&sessions.legacy)
+                    .context("Failed to remove legacy session file")?;
+
+                info!("Moved Last.fm session to {location}");
+            }
+
+            Some(name)
+        }
+        Err(err) => match err.downcast_ref::<ApiError>() {
+            Some(ApiError {
+                code: codes::INVALID_SESSION_KEY,
+                ..
+            }) => bail!(
+                "The Last.fm session was revoked or is invalid. \
+                Run `rescrobbled auth lastfm` to log in again"
+            ),
+            _ if is_legacy && username.is_some() => {
+                return Err(err
+                    .context("Could not check which account the Last.fm session file belongs to"));
+            }
+            // Don't fail if e.g. the network is not up yet
+            _ => {
+                warn!("Could not validate the Last.fm session: {err:#}");
+                stored_username
+            }
+        },
+    };
+
+    Ok((session_key, name))
+}
+
+/// A client for a Last.fm account.
+pub struct Client {
+    api: Api,
+    session_key: String,
+    /// The name of the account, if it is configured or known from the session.
+    username: Option<String>,
+}
+
+impl Client {
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn now_playing(&self, track: &Track) -> Result<()> {
+        self.api.update_now_playing(&self.session_key, track)
+    }
+
+    /// Scrobble up to 50 tracks, each with the time it started playing,
+    /// in seconds since the UNIX epoch.
+    ///
+    /// Returns the result of each scrobble: the track as Last.fm corrected it, if it did,
+    /// or the reason it was ignored.
+    pub fn scrobble(
+        &self,
+        scrobbles: &[(&Track, u64)],
+    ) -> Result<Vec<Result<Option<Track>, IgnoredError>>> {
+        self.api.scrobble(&self.session_key, scrobbles)
+    }
+}
+
+#[cfg(test)]
+pub use api::tests::{scrobble_response, serve};
+
+#[cfg(test)]
+impl Client {
+    /// A client for a mock API at `url`.
+    pub fn with_url(url: &str) -> Self {
+        Self {
+            api: Api::with_url(url, "key", "secret"),
+            session_key: "session".to_owned(),
+            username: None,
+        }
+    }
+}
+
+/// Authenticate with Last.fm using a stored session.
+pub fn authenticate(
+    key: &str,
+    secret: &str,
+    username: Option<&str>,
+    store: SessionStore,
+) -> Result<Client> {
+    let api = Api::new(key, secret);
+
+    let (session_key, name) = load_session(&api, &Sessions::new(key, store)?, username)?;
+
+    Ok(Client {
+        api,
+        session_key,
+        username: username.map(str::to_owned).or(name),
+    })
+}
+
+/// Log in to Last.fm by letting the user authorize rescrobbled in a browser,
+/// and store the session key.
+pub fn login(key: &str, secret: &str, username: Option<&str>, store: SessionStore) -> Result<()> {
+    let api = Api::new(key, secret);
+    let sessions = Sessions::new(key, store)?;
+
+    let token = api.get_token().context("Failed to request a token")?;
+
+    println!(
+        "Open the following URL in a browser and allow rescrobbled to access your Last.fm account:\n\n\
+        {}\n\n\
+        Waiting for authorization...",
+        api.auth_url(&token)
+    );
+
+    let started = Instant::now();
+
+    let session = loop {
+        thread::sleep(POLL_INTERVAL);
+
+        match api.get_session(&token) {
+            Ok(session) => break session,
+            Err(err) => match err.downcast_ref::<ApiError>() {
+                Some(ApiError {
+                    code: codes::UNAUTHORIZED_TOKEN,
+                    ..
+                }) if started.elapsed() < TOKEN_LIFETIME => continue,
+                Some(ApiError {
+                    code: codes::UNAUTHORIZED_TOKEN | codes::TOKEN_EXPIRED,
+                    ..
+                }) => bail!("The authorization request expired, please try again"),
+                _ => return Err(err.context("Failed to create a session")),
+            },
+        }
+    };
+
+    let location = sessions.save(&session.name, &session.key)?;
+
+    println!(
+        "Logged in to Last.fm as {}, the session is stored in {location}",
+        session.name
+    );
+
+    if let Some(username) = username
+        && username != session.name
+    {
+        warn!(
+            "`lastfm-username` is set to {username}, so the session for {} will not be used",
+            session.name
+        );
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::api::tests::serve;
+    use super::*;
+
+    #[test]
+    fn test_find_session() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("key");
+
+        assert_eq!(find_session(&dir, None).unwrap(), None);
+
+        write_session(&dir, "alice", "abc").unwrap();
+        assert_eq!(find_session(&dir, None).unwrap(), Some(dir.join("alice")));
+        assert_eq!(find_session(&dir, Some("bob")).unwrap(), None);
+
+        write_session(&dir, "bob", "def").unwrap();
+        assert!(find_session(&dir, None).is_err());
+        assert_eq!(
+            find_session(&dir, Some("bob")).unwrap(),
+            Some(dir.join("bob"))
+        );
+
+        // Session files are only readable by the user
+        let mode = fs::metadata(dir.join("bob")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Usernames can't refer to other files
+        for username in ["", ".", "..", "../session", "a\0b"] {
+            assert!(write_session(&dir, username, "ghi").is_err());
+            assert!(find_session(&dir, Some(username)).is_err());
+        }
+        assert!(!temp_dir.path().join("session").exists());
+    }
+
+    #[test]
+    fn test_load_session() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let sessions = Sessions {
+            key: "key".to_owned(),
+            dir: temp_dir.path().join("key"),
+            legacy: temp_dir.path().join("session"),
+            secret_service: None,
+        };
+        let (dir, legacy) = (&sessions.dir, &sessions.legacy);
+
+        let (url, handle) = serve(&[
+            (200, r#"{"user": {"name": "alice", "playcount": "1"}}"#),
+            (200, r#"{"user": {"name": "alice", "playcount": "1"}}"#),
+            (403, r#"{"error": 9, "message": "Invalid session key"}"#),
+            (500, "Internal Server Error"),
+        ]);
+        let api = Api::with_url(&url, "key", "secret");
+
+        assert!(load_session(&api, &sessions, None).is_err());
+
+        // A valid legacy session is moved
+        fs::write(legacy, "abc\n").unwrap();
+        assert_eq!(
+            load_session(&api, &sessions, None).unwrap(),
+            ("abc".to_owned(), Some("alice".to_owned()))
+        );
+        assert!(!legacy.exists());
+        assert_eq!(fs::read_to_string(dir.join("alice")).unwrap(), "abc");
+
+        // A legacy session of another account is not used for the configured account
+        fs::write(legacy, "def\n").unwrap();
+        let err = load_session(&api, &sessions, Some("bob")).unwrap_err();
+        assert!(err.to_string().contains("belongs to alice, not to bob"));
+        assert!(legacy.exists());
+        assert!(!dir.join("bob").exists());
+        fs::remove_file(legacy).unwrap();
+
+        // Revoked session
+        let err = load_session(&api, &sessions, Some("alice")).unwrap_err();
+        assert!(err.to_string().contains("auth lastfm"));
+
+        // Session that could not be validated, the account is known from the file name
+        assert_eq!(
+            load_session(&api, &sessions, None).unwrap(),
+            ("abc".to_owned(), Some("alice".to_owned()))
+        );
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].contains("method=user.getInfo"));
+        assert!(requests[0].contains("sk=abc"));
+    }
+}
@@ -0,0 +1,638 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Minimal client for the parts of the Last.fm API that rescrobbled uses.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+
+use attohttpc::header::RETRY_AFTER;
+
+use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+
+use serde_json::Value;
+
+use crate::service::retry::{ErrorKind, HttpError};
+use crate::track::Track;
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Error codes returned by the Last.fm API (see <https://www.last.fm/api/errorcodes>).
+pub mod codes {
+    pub const AUTHENTICATION_FAILED: u32 = 4;
+    pub const OPERATION_FAILED: u32 = 8;
+    pub const INVALID_SESSION_KEY: u32 = 9;
+    pub const INVALID_API_KEY: u32 = 10;
+    pub const SERVICE_OFFLINE: u32 = 11;
+    pub const UNAUTHORIZED_TOKEN: u32 = 14;
+    pub const TOKEN_EXPIRED: u32 = 15;
+    pub const TEMPORARILY_UNAVAILABLE: u32 = 16;
+    pub const SUSPENDED_API_KEY: u32 = 26;
+    pub const RATE_LIMIT_EXCEEDED: u32 = 29;
+}
+
+/// An error returned by the Last.fm API.
+#[derive(Debug, PartialEq)]
+pub struct ApiError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (error {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    /// Classify the error, to decide if the request should be retried.
+    pub fn kind(&self) -> ErrorKind {
+        match self.code {
+            codes::OPERATION_FAILED | codes::SERVICE_OFFLINE | codes::TEMPORARILY_UNAVAILABLE => {
+                ErrorKind::Server
+            }
+            codes::RATE_LIMIT_EXCEEDED => ErrorKind::RateLimited,
+            codes::AUTHENTICATION_FAILED
+            | codes::INVALID_SESSION_KEY
+            | codes::INVALID_API_KEY
+            | codes::UNAUTHORIZED_TOKEN
+            | codes::TOKEN_EXPIRED
+            | codes::SUSPENDED_API_KEY => ErrorKind::Auth,
+            _ => ErrorKind::Rejected,
+        }
+    }
+}
+
+/// The reason Last.fm ignored a scrobble (see <https://www.last.fm/api/show/track.scrobble>).
+#[derive(Debug, PartialEq)]
+pub struct IgnoredError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl fmt::Display for IgnoredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.code {
+            _ if !self.message.is_empty() => &self.message,
+            1 => "Artist was ignored",
+            2 => "Track was ignored",
+            3 => "Timestamp was too old",
+            4 => "Timestamp was too new",
+            5 => "Daily scrobble limit exceeded",
+            _ => "Unknown reason",
+        };
+
+        write!(f, "Scrobble ignored: {reason} (code {})", self.code)
+    }
+}
+
+impl std::error::Error for IgnoredError {}
+
+impl IgnoredError {
+    /// Classify the error, to decide if the scrobble should be retried.
+    pub fn kind(&self) -> ErrorKind {
+        match self.code {
+            // The scrobble can be submitted again tomorrow
+            5 => ErrorKind::RateLimited,
+            _ => ErrorKind::Rejected,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: u32,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct User {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ScrobbleResponse {
+    scrobbles: Scrobbles,
+}
+
+#[derive(Deserialize)]
+struct Scrobbles {
+    scrobble: OneOrMany<ScrobbleResult>,
+}
+
+/// Last.fm returns a single object instead of an array with one element.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScrobbleResult {
+    artist: Correctable,
+    track: Correctable,
+    album: Correctable,
+    ignored_message: IgnoredMessage,
+}
+
+impl ScrobbleResult {
+    /// The track as Last.fm recorded it, if it was corrected.
+    fn corrected(&self) -> Option<Track> {
+        [&self.artist, &self.track, &self.album]
+            .iter()
+            .any(|field| number(&field.corrected) != 0)
+            .then(|| {
+                let album = Some(self.album.text.as_str()).filter(|album| !album.is_empty());
+                Track::new(&self.artist.text, &self.track.text, album)
+            })
+    }
+}
+
+#[derive(Deserialize)]
+struct Correctable {
+    corrected: Value,
+    #[serde(rename = "#text", default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct IgnoredMessage {
+    code: Value,
+    #[serde(rename = "#text", default)]
+    message: String,
+}
+
+/// Read a number that Last.fm may return as a string.
+fn number(value: &Value) -> u32 {
+    match value {
+        Value::String(value) => value.parse().unwrap_or_default(),
+        value => value.as_u64().unwrap_or_default() as u32,
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionResponse {
+    session: Session,
+}
+
+/// A Last.fm session, which can be used to authenticate indefinitely.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Session {
+    pub name: String,
+    pub key: String,
+}
+
+pub struct Api {
+    url: String,
+    key: String,
+    secret: String,
+}
+
+impl Api {
+    pub fn new(key: &str, secret: &str) -> Self {
+        Self {
+            url: API_URL.to_owned(),
+            key: key.to_owned(),
+            secret: secret.to_owned(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_url(url: &str, key: &str, secret: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            key: key.to_owned(),
+            secret: secret.to_owned(),
+        }
+    }
+
+    /// Compute the signature of a request (see <https://www.last.fm/api/authspec#_8-signing-calls>).
+    fn signature(&self, params: &BTreeMap<&str, &str>) -> String {
+        let mut input = String::new();
+
+        for (name, value) in params {
+            if *name != "format" && *name != "callback" {
+                input.push_str(name);
+                input.push_str(value);
+            }
+        }
+
+        input.push_str(&self.secret);
+
+        format!("{:x}", md5::compute(input))
+    }
+
+    /// Call an API method using a signed request, and decode the response.
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: &[(&str, &str)]) -> Result<T> {
+        let mut params: BTreeMap<&str, &str> = params.iter().copied().collect();
+        params.insert("method", method);
+        params.insert("api_key", &self.key);
+
+        let signature = self.signature(&params);
+        params.insert("api_sig", &signature);
+        params.insert("format", "json");
+
+        let response = attohttpc::post(&self.url)
+            .timeout(TIMEOUT)
+            .form(&params)?
+            .send()
+            .with_context(|| format!("Failed to call {method}"))?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+            .map(Duration::from_secs);
+
+        let body = response
+            .text()
+            .with_context(|| format!("Failed to call {method}"))?;
+
+        // Errors are returned with a non-success status code,
+        // but the body contains the actual error
+        if let Ok(error) = serde_json::from_str::<ErrorResponse>(&body) {
+            return Err(ApiError {
+                code: error.error,
+                message: error.message,
+            }
+            .into());
+        }
+
+        if !status.is_success() {
+            return Err(anyhow::Error::new(HttpError {
+                status: status.as_u16(),
+                retry_after,
+            })
+            .context(format!("Failed to call {method}")));
+        }
+
+        serde_json::from_str(&body).with_context(|| format!("Invalid response to {method}"))
+    }
+
+    /// Request a token to start the authentication flow.
+    pub fn get_token(&self) -> Result<String> {
+        let response: TokenResponse = self.call("auth.getToken", &[])?;
+        Ok(response.token)
+    }
+
+    /// The URL where the user can authorize a token.
+    pub fn auth_url(&self, token: &str) -> String {
+        format!("{AUTH_URL}?api_key={}&token={token}", self.key)
+    }
+
+    /// Exchange an authorized token for a session.
+    pub fn get_session(&self, token: &str) -> Result<Session> {
+        let response: SessionResponse = self.call("auth.getSession", &[("token", token)])?;
+        Ok(response.session)
+    }
+
+    /// Get the name of the user that a session belongs to. This fails if the session is invalid.
+    pub fn get_user_name(&self, session_key: &str) -> Result<String> {
+        let response: UserResponse = self.call("user.getInfo", &[("sk", session_key)])?;
+        Ok(response.user.name)
+    }
+
+    /// Tell Last.fm which track the user is listening to.
+    pub fn update_now_playing(&self, session_key: &str, track: &Track) -> Result<()> {
+        let mut params = vec![
+            ("sk", session_key),
+            ("artist", track.artist()),
+            ("track", track.title()),
+        ];
+        if let Some(album) = track.album() {
+            params.push(("album", album));
+        }
+
+        self.call::<IgnoredAny>("track.updateNowPlaying", &params)?;
+
+        Ok(())
+    }
+
+    /// Scrobble up to 50 tracks, each with the time it started playing,
+    /// in seconds since the UNIX epoch.
+    ///
+    /// Returns the result of each scrobble: the track as Last.fm corrected it, if it did,
+    /// or the reason it was ignored.
+    pub fn scrobble(
+        &self,
+        session_key: &str,
+        scrobbles: &[(&Track, u64)],
+    ) -> Result<Vec<Result<Option<Track>, IgnoredError>>> {
+        let mut params = vec![("sk".to_owned(), session_key.to_owned())];
+
+        for (index, (track, timestamp)) in scrobbles.iter().enumerate() {
+            params.push((format!("artist[{index}]"), track.artist().to_owned()));
+            params.push((format!("track[{index}]"), track.title().to_owned()));
+            params.push((format!("timestamp[{index}]"), timestamp.to_string()));
+            if let Some(album) = track.album() {
+                params.push((format!("album[{index}]"), album.to_owned()));
+            }
+        }
+
+        let params: Vec<_> = params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+
+        let response: ScrobbleResponse = self.call("track.scrobble", &params)?;
+
+        let results = match response.scrobbles.scrobble {
+            OneOrMany::One(result) => vec![result],
+            OneOrMany::Many(results) => results,
+        };
+
+        if results.len() != scrobbles.len() {
+            bail!(
+                "Last.fm returned {} results for {} scrobbles",
+                results.len(),
+                scrobbles.len()
+            );
+        }
+
+        let results = results
+            .into_iter()
+            .map(|result| match number(&result.ignored_message.code) {
+                0 => Ok(result.corrected()),
+                code => Err(IgnoredError {
+                    code,
+                    message: result.ignored_message.message,
+                }),
+            })
+            .collect();
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    /// Serve the given responses to consecutive requests on a local port.
+    /// The join handle returns the bodies of the requests.
+    pub fn serve(responses: &[(u16, &str)]) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let responses: Vec<_> = responses
+            .iter()
+            .map(|(status, body)| (*status, body.to_string()))
+            .collect();
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    if let Some((name, value)) = line.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let mut request = vec![0; length];
+                reader.read_exact(&mut request).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    /// A response to `track.scrobble`, with a scrobble for each given result:
+    /// `None` if it was accepted, or the code and message if it was ignored.
+    pub fn scrobble_response(results: &[Option<(u32, &str)>]) -> String {
+        let scrobbles: Vec<_> = results
+            .iter()
+            .map(|result| {
+                let (code, message) = result.unwrap_or((0, ""));
+                serde_json::json!({
+                    "artist": {"corrected": "0", "#text": "Artist"},
+                    "album": {"corrected": "0"},
+                    "track": {"corrected": "0", "#text": "Title"},
+                    "albumArtist": {"corrected": "0", "#text": ""},
+                    "timestamp": "1790812800",
+                    "ignoredMessage": {"code": code.to_string(), "#text": message}
+                })
+            })
+            .collect();
+
+        serde_json::json!({"scrobbles": {"scrobble": scrobbles}}).to_string()
+    }
+
+    #[test]
+    fn test_signature() {
+        let api = Api::new("key", "secret");
+
+        let params = BTreeMap::from([
+            ("method", "auth.getSession"),
+            ("api_key", "key"),
+            ("token", "token"),
+            ("format", "json"),
+        ]);
+
+        assert_eq!(
+            api.signature(&params),
+            format!(
+                "{:x}",
+                md5::compute("api_keykeymethodauth.getSessiontokentokensecret")
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_session() {
+        let (url, handle) = serve(&[
+            (
+                403,
+                r#"{"error": 14, "message": "Unauthorized Token - This token has not been issued"}"#,
+            ),
+            (
+                200,
+                r#"{"session": {"name": "user", "key": "abc123", "subscriber": 0}}"#,
+            ),
+        ]);
+
+        let api = Api::with_url(&url, "key", "secret");
+
+        let err = api.get_session("token").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>().map(|err| err.code),
+            Some(codes::UNAUTHORIZED_TOKEN)
+        );
+
+        assert_eq!(
+            api.get_session("token").unwrap(),
+            Session {
+                name: "user".to_owned(),
+                key: "abc123".to_owned(),
+            }
+        );
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].contains("method=auth.getSession"));
+        assert!(requests[0].contains("token=token"));
+        assert!(requests[0].contains("api_sig="));
+    }
+
+    #[test]
+    fn test_scrobble() {
+        let (url, handle) = serve(&[
+            (
+                200,
+                r##"{"scrobbles": {"@attr": {"accepted": 1, "ignored": 1}, "scrobble": [
+                    {
+                        "artist": {"corrected": "1", "#text": "Portishead"},
+                        "album": {"corrected": "0", "#text": "Dummy"},
+                        "track": {"corrected": "0", "#text": "Roads"},
+                        "albumArtist": {"corrected": "0", "#text": ""},
+                        "timestamp": "1790812800",
+                        "ignoredMessage": {"code": "0", "#text": ""}
+                    },
+                    {
+                        "artist": {"corrected": "0", "#text": "portishead"},
+                        "album": {"corrected": "0"},
+                        "track": {"corrected": "0", "#text": "Roads"},
+                        "albumArtist": {"corrected": "0", "#text": ""},
+                        "timestamp": "1690813100",
+                        "ignoredMessage": {"code": "3", "#text": "Timestamp too old"}
+                    }
+                ]}}"##,
+            ),
+            (
+                200,
+                r##"{"scrobbles": {"@attr": {"accepted": 1, "ignored": 0}, "scrobble": {
+                    "artist": {"corrected": "0", "#text": "portishead"},
+                    "album": {"corrected": "0", "#text": "Dummy"},
+                    "track": {"corrected": "0", "#text": "Roads"},
+                    "albumArtist": {"corrected": "0", "#text": ""},
+                    "timestamp": "1790812800",
+                    "ignoredMessage": {"code": "0", "#text": ""}
+                }}}"##,
+            ),
+            (
+                200,
+                r##"{"scrobbles": {"@attr": {"accepted": 1, "ignored": 0}, "scrobble": {
+                    "artist": {"corrected": "1", "#text": "Portishead"},
+                    "album": {"corrected": "0"},
+                    "track": {"corrected": "0", "#text": "Roads"},
+                    "albumArtist": {"corrected": "0", "#text": ""},
+                    "timestamp": "1790812800",
+                    "ignoredMessage": {"code": "0", "#text": ""}
+                }}}"##,
+            ),
+            (
+                403,
+                r#"{"error": 9, "message": "Invalid session key - Please re-authenticate"}"#,
+            ),
+            (503, "<html>Service Unavailable</html>"),
+        ]);
+
+        let api = Api::with_url(&url, "key", "secret");
+        let track = Track::new("portishead", "Roads", Some("Dummy"));
+        let scrobbles = [(&track, 1_790_812_800), (&track, 1_690_813_100)];
+
+        let results = api.scrobble("abc", &scrobbles).unwrap();
+        assert_eq!(
+            results,
+            vec![
+                Ok(Some(Track::new("Portishead", "Roads", Some("Dummy")))),
+                Err(IgnoredError {
+                    code: 3,
+                    message: "Timestamp too old".to_owned()
+                }),
+            ]
+        );
+        assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::Rejected);
+
+        assert_eq!(
+            api.scrobble("abc", &scrobbles[..1]).unwrap(),
+            vec![Ok(None)]
+        );
+
+        // A missing album is not recorded as an empty one
+        assert_eq!(
+            api.scrobble("abc", &scrobbles[..1]).unwrap(),
+            vec![Ok(Some(Track::new("Portishead", "Roads", None)))]
+        );
+
+        let err = api.scrobble("abc", &scrobbles).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ApiError>().map(ApiError::kind),
+            Some(ErrorKind::Auth)
+        );
+
+        let err = api.scrobble("abc", &scrobbles).unwrap_err();
+        assert_eq!(
+            err.root_cause()
+                .downcast_ref::<HttpError>()
+                .map(|err| err.status),
+            Some(503)
+        );
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].contains("method=track.scrobble"));
+        assert!(requests[0].contains("artist%5B1%5D=portishead"));
+        assert!(requests[0].contains("timestamp%5B1%5D=1690813100"));
+        assert!(requests[0].contains("sk=abc"));
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Classifying errors returned by the services, and deciding when to retry.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::Service;
+use super::lastfm::{ApiError, IgnoredError};
+
+/// The first retry happens after this delay, which doubles after each failure.
+const INITIAL_DELAY: Duration = Duration::from_secs(30);
+/// The maximum delay between retries.
+const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// What kind of failure an error is, which determines if and when to retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The service could not be reached.
+    Network,
+    /// The service had an internal error or is temporarily unavailable.
+    Server,
+    /// Too many requests were made.
+    RateLimited,
+    /// The session or token is invalid; retrying does not help until the user logs in again.
+    Auth,
+    /// The request was rejected, e.g. because of invalid parameters; it should never be retried.
+    Rejected,
+}
+
+impl ErrorKind {
+    /// Classify an HTTP status code that indicates a failure.
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => Self::Auth,
+            429 => Self::RateLimited,
+            408 => Self::Network,
+            400..=499 => Self::Rejected,
+            _ => Self::Server,
+        }
+    }
+}
+
+/// An HTTP request that failed with an error status and no further information.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: u16,
+    /// How long to wait before trying again, from the `Retry-After` header.
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP status {}", self.status)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Determine the kind of an error, and how long the service asked to wait, if it did.
+pub fn classify(err: &anyhow::Error) -> (ErrorKind, Option<Duration>) {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ApiError>() {
+            return (err.kind(), None);
+        }
+
+        if let Some(err) = cause.downcast_ref::<IgnoredError>() {
+            return (err.kind(), None);
+        }
+
+        if let Some(err) = cause.downcast_ref::<HttpError>() {
+            return (ErrorKind::from_status(err.status), err.retry_after);
+        }
+
+        if let Some(err) = cause.downcast_ref::<listenbrainz::Error>() {
+            let kind = match err {
+                listenbrainz::Error::Api { code, .. } => ErrorKind::from_status(*code),
+                // Usually an error page from a proxy instead of the API
+                listenbrainz::Error::Json(_) => ErrorKind::Server,
+                listenbrainz::Error::Http(_) => ErrorKind::Network,
+                listenbrainz::Error::InvalidToken | listenbrainz::Error::NotAuthenticated => {
+                    ErrorKind::Auth
+                }
+            };
+            return (kind, None);
+        }
+    }
+
+    // Other errors are assumed to be temporary, e.g. timeouts
+    (ErrorKind::Network, None)
+}
+
+/// Keeps track of consecutive failures of a service, to retry with exponential backoff.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+    /// Set after an authentication failure, which is not retried automatically.
+    paused: bool,
+}
+
+impl Backoff {
+    /// The delay before the next retry after `failures` consecutive failures, without jitter.
+    fn delay(failures: u32) -> Duration {
+        INITIAL_DELAY
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(MAX_DELAY)
+    }
+
+    /// Record a failed request. Returns how long to wait before retrying,
+    /// or `None` if the request should not be retried automatically.
+    pub fn failed(
+        &mut self,
+        kind: ErrorKind,
+        retry_after: Option<Duration>,
+        now: Instant,
+    ) -> Option<Duration> {
+        match kind {
+            // The service itself works fine
+            ErrorKind::Rejected => {
+                self.succeeded();
+                None
+            }
+            ErrorKind::Auth => {
+                self.paused = true;
+                None
+            }
+            ErrorKind::Network | ErrorKind::Server | ErrorKind::RateLimited => {
+                self.failures = self.failures.saturating_add(1);
+
+                // Spread out retries between half and the full delay,
+                // so clients don't retry at the same time
+                let delay = Self::delay(self.failures).mul_f64(fastrand::f64() * 0.5 + 0.5);
+                let delay = retry_after.map_or(delay, |retry_after| retry_after.max(delay));
+
+                self.retry_at = Some(now + delay);
+                Some(delay)
+            }
+        }
+    }
+
+    /// Record a successful request.
+    pub fn succeeded(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Determine if requests to the service should be held back, because an earlier
+    /// request failed and it is not yet time to retry.
+    pub fn is_waiting(&self, now: Instant) -> bool {
+        self.paused || self.retry_at.is_some_and(|retry_at| now < retry_at)
+    }
+
+    /// Determine if it is time to retry after an earlier failure.
+    /// Afterwards, requests are no longer held back until the next failure.
+    pub fn take_due(&mut self, now: Instant) -> bool {
+        if !self.paused && self.retry_at.is_some_and(|retry_at| now >= retry_at) {
+            self.retry_at = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The backoff state of each service, by account identifier, so it is kept when
+/// the config is reloaded.
+#[derive(Debug, Default)]
+pub struct Backoffs(HashMap<String, Backoff>);
+
+impl Backoffs {
+    /// Keep the backoff state of a service while the config is reloaded.
+    /// A service that was paused after an authentication failure is not kept,
+    /// so that it is re-created, e.g. with the session of a new login.
+    pub fn keep(&mut self, service: Service, backoff: Backoff) -> Option<Service> {
+        if backoff.paused {
+            return None;
+        }
+
+        self.0.insert(service.id(), backoff);
+        Some(service)
+    }
+
+    /// Take the backoff state of a service, or a new one if there is none.
+    pub fn take(&mut self, service: &Service) -> Backoff {
+        self.0.remove(&service.id()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Context, anyhow};
+
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let err = anyhow::Error::new(ApiError {
+            code: 9,
+            message: "Invalid session key".to_owned(),
+        })
+        .context("Failed to submit track to Last.fm");
+        assert_eq!(classify(&err), (ErrorKind::Auth, None));
+
+        let err = anyhow::Error::new(HttpError {
+            status: 429,
+            retry_after: Some(Duration::from_secs(60)),
+        });
+        assert_eq!(
+            classify(&err),
+            (ErrorKind::RateLimited, Some(Duration::from_secs(60)))
+        );
+
+        let err = Err::<(), _>(listenbrainz::Error::Api {
+            code: 503,
+            error: "Service Unavailable".to_owned(),
+        })
+        .context("Failed to submit track to ListenBrainz")
+        .unwrap_err();
+        assert_eq!(classify(&err), (ErrorKind::Server, None));
+
+        let err = Err::<(), _>(listenbrainz::Error::Api {
+            code: 400,
+            error: "Invalid listen".to_owned(),
+        })
+        .context("Failed to submit track to ListenBrainz")
+        .unwrap_err();
+        assert_eq!(classify(&err), (ErrorKind::Rejected, None));
+
+        let err = anyhow::Error::new(IgnoredError {
+            code: 1,
+            message: "Artist was ignored".to_owned(),
+        });
+        assert_eq!(classify(&err), (ErrorKind::Rejected, None));
+
+        assert_eq!(
+            classify(&anyhow!("Connection refused")),
+            (ErrorKind::Network, None)
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let now = Instant::now();
+        let mut backoff = Backoff::default();
+        assert!(!backoff.is_waiting(now));
+
+        let delay = backoff.failed(ErrorKind::Network, None, now).unwrap();
+        assert!(delay >= INITIAL_DELAY / 2 && delay <= INITIAL_DELAY);
+        assert!(backoff.is_waiting(now));
+        assert!(!backoff.take_due(now));
+
+        let delay = backoff.failed(ErrorKind::Server, None, now).unwrap();
+        assert!(delay >= INITIAL_DELAY && delay <= INITIAL_DELAY * 2);
+
+        assert!(backoff.take_due(now + INITIAL_DELAY * 2));
+        assert!(!backoff.is_waiting(now + INITIAL_DELAY * 2));
+        assert!(!backoff.take_due(now + INITIAL_DELAY * 2));
+
+        // The delay is capped, and the service may ask to wait longer
+        assert_eq!(Backoff::delay(30), MAX_DELAY);
+        let retry_after = Duration::from_secs(2 * 60 * 60);
+        assert_eq!(
+            backoff.failed(ErrorKind::RateLimited, Some(retry_after), now),
+            Some(retry_after)
+        );
+
+        backoff.succeeded();
+        assert!(!backoff.is_waiting(now));
+
+        // Permanent failures are not retried
+        assert_eq!(backoff.failed(ErrorKind::Rejected, None, now), None);
+        assert!(!backoff.is_waiting(now));
+
+        assert_eq!(backoff.failed(ErrorKind::Auth, None, now), None);
+        assert!(backoff.is_waiting(now + MAX_DELAY));
+        assert!(!backoff.take_due(now + MAX_DELAY));
+    }
+
+    #[test]
+    fn test_backoffs() {
+        let now = Instant::now();
+        let service = Service::mock_lastfm("http://127.0.0.1:9/");
+        let mut backoffs = Backoffs::default();
+
+        let mut backoff = Backoff::default();
+        backoff.failed(ErrorKind::Network, None, now);
+        let service = backoffs.keep(service, backoff).unwrap();
+        assert!(backoffs.take(&service).is_waiting(now));
+        assert!(!backoffs.take(&service).is_waiting(now));
+
+        // Paused services are re-created, with a new backoff state
+        let mut backoff = Backoff::default();
+        backoff.failed(ErrorKind::Auth, None, now);
+        assert!(backoffs.keep(service, backoff).is_none());
+        assert!(backoffs.0.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+/// Flags that are set asynchronously by Unix signal handlers
+/// and polled by the main loop.
+pub struct Signals {
+    reload: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Signals {
+    /// Register the signal handlers.
+    pub fn register() -> Result<Self> {
+        let reload = Arc::new(AtomicBool::new(false));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        signal_hook::flag::register(SIGHUP, Arc::clone(&reload))
+            .context("Failed to register SIGHUP handler")?;
+
+        for signal in [SIGTERM, SIGINT] {
+            // A second signal terminates immediately, in case shutting down hangs
+            signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&shutdown)))
+                .context("Failed to register shutdown signal handler")?;
+        }
+
+        Ok(Self { reload, shutdown })
+    }
+
+    /// Returns whether a reload was requested (using `SIGHUP`)
+    /// since the last call, and resets the request.
+    pub fn take_reload(&self) -> bool {
+        self.reload.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns whether a shutdown was requested (using `SIGTERM` or `SIGINT`).
+    pub fn should_exit(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+
+use serde::{Deserialize, Serialize};
+
+use crate::track::Track;
+
+const STATE_DIR: &str = "rescrobbled";
+const STATE_FILE: &str = "state.json";
+
+/// A session is only restored if it was saved less than this long ago.
+const MAX_SESSION_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// A scrobble that could not be submitted to a service yet, and could not be added to
+/// the history database. These are moved to the history database at startup.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PendingScrobble {
+    /// The identifier of the service account, see `Service::id`.
+    pub service: String,
+    /// The name of the service, as displayed by `Service`.
+    pub service_name: String,
+    pub track: Track,
+    /// Seconds since the UNIX epoch at which the track started playing.
+    pub timestamp: u64,
+}
+
+/// The track that was playing when rescrobbled exited.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Session {
+    /// The D-Bus bus name of the player.
+    pub player: String,
+    pub track: Track,
+    /// Play time of the track in milliseconds.
+    pub play_time: u64,
+    /// Seconds since the UNIX epoch at which the track started playing.
+    pub track_start: u64,
+    pub scrobbled: bool,
+    /// Seconds since the UNIX epoch at which the session was saved.
+    pub saved_at: u64,
+}
+
+impl Session {
+    /// Determine if this session is recent enough to be restored.
+    pub fn is_recent(&self) -> bool {
+        let saved_at = UNIX_EPOCH + Duration::from_secs(self.saved_at);
+
+        SystemTime::now()
+            .duration_since(saved_at)
+            .map(|age| age < MAX_SESSION_AGE)
+            .unwrap_or(false)
+    }
+}
+
+/// In-memory state that is persisted when rescrobbled exits.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct State {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<PendingScrobble>,
+    pub session: Option<Session>,
+}
+
+impl State {
+    /// Load the state from the given file. A missing file results in an empty state.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let buffer = fs::read_to_string(path).context("Failed to open state file")?;
+
+        serde_json::from_str(&buffer).context("Failed to parse state file")
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let buffer = serde_json::to_string(self).context("Failed to serialize state")?;
+
+        // Write to a temporary file first, so the state file is never left half-written
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, buffer).context("Failed to write state file")?;
+        fs::rename(&temp_path, path).context("Failed to write state file")
+    }
+
+    pub fn load() -> Result<Self> {
+        Self::load_from(&state_path()?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&state_path()?)
+    }
+}
+
+pub fn state_dir() -> Result<PathBuf> {
+    let mut path = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .ok_or_else(|| anyhow!("User state directory does not exist"))?;
+
+    path.push(STATE_DIR);
+
+    if !path.exists() {
+        fs::create_dir_all(&path).context("Failed to create state directory")?;
+    }
+
+    Ok(path)
+}
+
+fn state_path() -> Result<PathBuf> {
+    let mut path = state_dir()?;
+
+    path.push(STATE_FILE);
+
+    Ok(path)
+}
+
+/// Convert a `SystemTime` to seconds since the UNIX epoch.
+pub fn unix_timestamp(time: &SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(STATE_FILE);
+
+        // A missing state file should result in an empty state
+
+        assert_eq!(State::load_from(&path).unwrap(), State::default());
+
+        let state = State {
+            pending: vec![PendingScrobble {
+                service: "lastfm:key:alice".to_owned(),
+                service_name: "Last.fm (alice)".to_owned(),
+                track: Track::new("Daft Punk", "Veridis Quo", Some("Discovery")),
+                timestamp: 1_000_000_000,
+            }],
+            session: Some(Session {
+                player: "org.mpris.MediaPlayer2.spotify".to_owned(),
+                track: Track::new("Daft Punk", "Digital Love", None),
+                play_time: 12_500,
+                track_start: 1_000_000_300,
+                scrobbled: false,
+                saved_at: unix_timestamp(&SystemTime::now()),
+            }),
+        };
+
+        state.save_to(&path).unwrap();
+
+        let loaded = State::load_from(&path).unwrap();
+        assert_eq!(loaded, state);
+        assert!(loaded.session.unwrap().is_recent());
+
+        // Saving again replaces the file, without leaving the temporary file behind
+        State::default().save_to(&path).unwrap();
+        assert_eq!(State::load_from(&path).unwrap(), State::default());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+}
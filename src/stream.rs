@@ -0,0 +1,161 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Internet radio streams, which report the artist and title of the current song
+//! combined in the title (like `Artist - Title`), without an artist or length.
+
+use mpris::Metadata;
+
+use crate::track::Track;
+
+/// URL schemes of network streams.
+const STREAM_SCHEMES: &[&str] = &[
+    "http", "https", "icy", "icyx", "mms", "mmsh", "rtsp", "rtmp",
+];
+
+/// The separators between the artist and title, unless `stream-title-separators` is set.
+pub const DEFAULT_SEPARATORS: &[&str] = &[" - ", " – ", " — "];
+
+/// Determine if the metadata describes a network stream: a track with a network URL
+/// and without a length.
+pub fn is_stream(metadata: &Metadata) -> bool {
+    let is_network = metadata
+        .url()
+        .and_then(|url| url.split_once("://"))
+        .is_some_and(|(scheme, _)| STREAM_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()));
+
+    is_network && metadata.length().is_none_or(|length| length.is_zero())
+}
+
+/// Split a combined title into the artist and title, at the first separator that occurs in it.
+pub fn split_title<'t, S: AsRef<str>>(
+    title: &'t str,
+    separators: &[S],
+) -> Option<(&'t str, &'t str)> {
+    separators
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|separator| !separator.is_empty())
+        .filter_map(|separator| {
+            title
+                .find(separator)
+                .map(|index| (index, &title[..index], &title[index + separator.len()..]))
+        })
+        .min_by_key(|(index, _, _)| *index)
+        .map(|(_, artist, title)| (artist.trim(), title.trim()))
+        .filter(|(artist, title)| !artist.is_empty() && !title.is_empty())
+}
+
+/// Get the current song of a stream. If the player did not report an artist, it is parsed
+/// from the title; if that fails, the title is kept and the artist is left empty.
+pub fn track_from_metadata<S: AsRef<str>>(metadata: &Metadata, separators: &[S]) -> Track {
+    let track = Track::from_metadata(metadata);
+
+    if !track.artist().is_empty() {
+        return track;
+    }
+
+    match split_title(track.title(), separators) {
+        Some((artist, title)) => Track::new(artist, title, track.album()),
+        None => track,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mpris::MetadataValue;
+
+    use super::*;
+
+    fn metadata(url: &str, title: &str, length: Option<i64>) -> Metadata {
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "xesam:url".to_owned(),
+            MetadataValue::String(url.to_owned()),
+        );
+        metadata.insert(
+            "xesam:title".to_owned(),
+            MetadataValue::String(title.to_owned()),
+        );
+        if let Some(length) = length {
+            metadata.insert("mpris:length".to_owned(), MetadataValue::I64(length));
+        }
+        Metadata::from(metadata)
+    }
+
+    #[test]
+    fn test_is_stream() {
+        assert!(is_stream(&metadata(
+            "https://ice1.somafm.com/groovesalad-128-mp3",
+            "Boards of Canada - Roygbiv",
+            None
+        )));
+        assert!(is_stream(&metadata(
+            "ICY://radio.example/live",
+            "",
+            Some(0)
+        )));
+        assert!(!is_stream(&metadata(
+            "https://www.youtube.com/watch?v=abc",
+            "Roygbiv",
+            Some(150_000_000)
+        )));
+        assert!(!is_stream(&metadata(
+            "file:///music/roygbiv.flac",
+            "Roygbiv",
+            None
+        )));
+    }
+
+    #[test]
+    fn test_split_title() {
+        assert_eq!(
+            split_title("Boards of Canada - Roygbiv", DEFAULT_SEPARATORS),
+            Some(("Boards of Canada", "Roygbiv"))
+        );
+        // The first separator in the title is used
+        assert_eq!(
+            split_title("Sigur Rós – Hoppípolla - Live", DEFAULT_SEPARATORS),
+            Some(("Sigur Rós", "Hoppípolla - Live"))
+        );
+        assert_eq!(
+            split_title("Kraftwerk / Computer Love", &[" / "]),
+            Some(("Kraftwerk", "Computer Love"))
+        );
+        assert_eq!(split_title("Radio Paradise", DEFAULT_SEPARATORS), None);
+        assert_eq!(split_title(" - Jingle", DEFAULT_SEPARATORS), None);
+    }
+
+    #[test]
+    fn test_track_from_metadata() {
+        let track = track_from_metadata(
+            &metadata(
+                "http://radio.example/stream",
+                "Air - La femme d'argent",
+                None,
+            ),
+            DEFAULT_SEPARATORS,
+        );
+        assert_eq!(track, Track::new("Air", "La femme d'argent", None));
+
+        let track = track_from_metadata(
+            &metadata("http://radio.example/stream", "News at 9", None),
+            DEFAULT_SEPARATORS,
+        );
+        assert_eq!(track, Track::new("", "News at 9", None));
+    }
+}
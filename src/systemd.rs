@@ -0,0 +1,179 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Support for the systemd service notification protocol (see `sd_notify(3)`).
+
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+struct Watchdog {
+    interval: Duration,
+    last_ping: Option<Instant>,
+}
+
+/// Sends notifications to the service manager, if rescrobbled was started
+/// by systemd with `Type=notify`. Otherwise, all notifications are no-ops.
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+    watchdog: Option<Watchdog>,
+}
+
+impl Notifier {
+    /// Create a notifier from the `$NOTIFY_SOCKET` and `$WATCHDOG_USEC` environment variables.
+    pub fn from_environment() -> Self {
+        let socket = env::var(NOTIFY_SOCKET).ok();
+
+        // The watchdog is meant for this process only if `$WATCHDOG_PID` is unset or matches
+        let watchdog_usec = env::var(WATCHDOG_USEC)
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| match env::var(WATCHDOG_PID) {
+                Ok(pid) => pid.parse() == Ok(std::process::id()),
+                Err(_) => true,
+            });
+
+        let notifier = Self::new(socket.as_deref(), watchdog_usec);
+
+        // Prevent child processes (e.g. the filter script) from inheriting these
+        // Safety: this is called at startup, before any other threads are spawned
+        unsafe {
+            env::remove_var(NOTIFY_SOCKET);
+            env::remove_var(WATCHDOG_USEC);
+            env::remove_var(WATCHDOG_PID);
+        }
+
+        notifier
+    }
+
+    fn new(socket: Option<&str>, watchdog_usec: Option<u64>) -> Self {
+        let socket = socket.and_then(|socket| {
+            let addr = match socket.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name),
+                None => SocketAddr::from_pathname(socket),
+            };
+
+            match (UnixDatagram::unbound(), addr) {
+                (Ok(datagram), Ok(addr)) => Some((datagram, addr)),
+                (Err(err), _) | (_, Err(err)) => {
+                    warn!("Failed to connect to systemd notification socket: {err}");
+                    None
+                }
+            }
+        });
+
+        let watchdog = watchdog_usec.filter(|usec| *usec > 0).map(|usec| Watchdog {
+            interval: Duration::from_micros(usec),
+            last_ping: None,
+        });
+
+        Self { socket, watchdog }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Some((ref datagram, ref addr)) = self.socket
+            && let Err(err) = datagram.send_to_addr(state.as_bytes(), addr)
+        {
+            warn!("Failed to notify systemd: {err}");
+        }
+    }
+
+    /// Notify that startup has finished.
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    /// Notify that the config is being reloaded. Call `ready` when done.
+    pub fn reloading(&self) {
+        self.notify("RELOADING=1");
+    }
+
+    /// Notify that rescrobbled is shutting down.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Update the status shown by `systemctl status`.
+    pub fn status(&self, status: &str) {
+        // Newlines would end the assignment
+        self.notify(&format!("STATUS={}", status.replace('\n', " ")));
+    }
+
+    /// Ping the watchdog, if enabled. This can be called often;
+    /// pings are only sent at half the watchdog interval.
+    pub fn watchdog(&mut self) {
+        let Some(ref mut watchdog) = self.watchdog else {
+            return;
+        };
+
+        let due = watchdog
+            .last_ping
+            .map(|last_ping| last_ping.elapsed() >= watchdog.interval / 2)
+            .unwrap_or(true);
+
+        if due {
+            watchdog.last_ping = Some(Instant::now());
+            self.notify("WATCHDOG=1");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 256];
+        let len = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_notify() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("notify.sock");
+
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let mut notifier = Notifier::new(path.to_str(), Some(60_000_000));
+
+        notifier.ready();
+        assert_eq!(receive(&socket), "READY=1");
+
+        notifier.status("Now playing:\nsomething");
+        assert_eq!(receive(&socket), "STATUS=Now playing: something");
+
+        // Only the first watchdog ping within half the interval should be sent
+
+        notifier.watchdog();
+        notifier.watchdog();
+        assert_eq!(receive(&socket), "WATCHDOG=1");
+        assert!(socket.recv(&mut [0; 256]).is_err());
+
+        // Without a socket, notifying should do nothing
+
+        let mut notifier = Notifier::new(None, None);
+        notifier.ready();
+        notifier.watchdog();
+    }
+}
@@ -15,7 +15,9 @@
 
 use mpris::Metadata;
 
-#[derive(Debug, Default, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Track {
     artist: String,
     title: String,
@@ -49,6 +51,10 @@ impl Track {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.artist.is_empty() && self.title.is_empty() && self.album.is_none()
+    }
+
     pub fn clear(&mut self) {
         self.artist.clear();
         self.title.clear();
@@ -0,0 +1,849 @@
+// Copyright (C) 2026 Koen Bolhuis
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Submitting to the services on worker threads, so the main loop never waits for the network.
+//! Each service has a thread of its own, so a slow service does not delay the others.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+
+use log::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::history::{History, PendingScrobble, Play, Status};
+use crate::service::Service;
+use crate::service::retry::{self, Backoff, Backoffs, ErrorKind};
+use crate::state::{self, unix_timestamp};
+use crate::track::Track;
+
+/// How often to check if failed requests should be retried, while there is nothing else to do.
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait before trying again to add plays to the history, after it failed.
+const RECORD_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+enum Message {
+    /// Add a play that was not scrobbled to the history.
+    Record(Play),
+    /// Add a play to the history and submit it to all services.
+    Scrobble { play: Play, timestamp: SystemTime },
+    /// Update the track that is playing, or `None` if nothing is playing.
+    NowPlaying(Option<Track>),
+    /// Re-initialize the services whose settings changed after the config was reloaded.
+    Reload(Arc<Config>),
+}
+
+/// A request to the thread of a single service.
+enum Request {
+    /// Submit a play that was added to the history.
+    Submit {
+        play: i64,
+        track: Track,
+        timestamp: SystemTime,
+    },
+    /// Update the track that is playing, or `None` if nothing is playing.
+    NowPlaying(Option<Track>),
+}
+
+/// A handle to the worker thread, which records plays in the history and passes
+/// the requests to the services on to their own threads.
+pub struct Worker {
+    sender: Sender<Message>,
+    handle: JoinHandle<Vec<state::PendingScrobble>>,
+}
+
+impl Worker {
+    pub fn spawn(services: Vec<Service>, history: History) -> Result<Self> {
+        let history = Arc::new(Mutex::new(history));
+
+        let submitters = services
+            .into_iter()
+            .map(|service| Submitter::spawn(service, Backoff::default(), history.clone(), None))
+            .collect::<Result<_>>()?;
+
+        let (sender, receiver) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("worker".to_owned())
+            .spawn(move || run(receiver, submitters, history))
+            .context("Failed to start worker thread")?;
+
+        Ok(Self { sender, handle })
+    }
+
+    fn send(&self, message: Message) {
+        if self.sender.send(message).is_err() {
+            error!("The worker thread stopped unexpectedly");
+        }
+    }
+
+    /// Add a play that was not scrobbled to the history.
+    pub fn record(&self, play: Play) {
+        self.send(Message::Record(play));
+    }
+
+    /// Add a play to the history and submit it to all services, with the time the track
+    /// started if given, or else the current time (not the time the request is made).
+    pub fn scrobble(&self, play: Play, track_start: Option<SystemTime>) {
+        let timestamp = track_start.unwrap_or_else(SystemTime::now);
+
+        self.send(Message::Scrobble { play, timestamp });
+    }
+
+    /// Submit a "now playing" request to all services, or forget the
+    /// previous track if `None`.
+    pub fn now_playing(&self, track: Option<Track>) {
+        self.send(Message::NowPlaying(track));
+    }
+
+    /// Re-initialize the services whose settings changed.
+    pub fn reload(&self, config: Arc<Config>) {
+        self.send(Message::Reload(config));
+    }
+
+    /// Wait for the requests that were already queued to finish, and stop the worker.
+    /// Returns the scrobbles that could not be added to the history, to save them
+    /// in the state file instead.
+    pub fn stop(self) -> Vec<state::PendingScrobble> {
+        drop(self.sender);
+
+        self.handle.join().unwrap_or_else(|_| {
+            error!("The worker thread panicked");
+            Vec::new()
+        })
+    }
+}
+
+/// A handle to the thread that makes the requests to a single service.
+struct Submitter {
+    id: String,
+    name: String,
+    sender: Sender<Request>,
+    handle: JoinHandle<(Service, Backoff)>,
+}
+
+impl Submitter {
+    fn spawn(
+        service: Service,
+        backoff: Backoff,
+        history: Arc<Mutex<History>>,
+        current_track: Option<Track>,
+    ) -> Result<Self> {
+        let (id, name) = (service.id(), service.to_string());
+        let (sender, receiver) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("submitter".to_owned())
+            .spawn(move || submit_requests(receiver, service, backoff, &history, current_track))
+            .with_context(|| format!("Failed to start submitter thread for {name}"))?;
+
+        Ok(Self {
+            id,
+            name,
+            sender,
+            handle,
+        })
+    }
+
+    fn send(&self, request: Request) {
+        if self.sender.send(request).is_err() {
+            error!(
+                "The submitter thread for {} stopped unexpectedly",
+                self.name
+            );
+        }
+    }
+
+    /// Wait for the requests that were already queued to finish, and stop the thread.
+    /// Returns the service and its backoff state, unless the thread panicked.
+    fn stop(self) -> Option<(Service, Backoff)> {
+        drop(self.sender);
+
+        self.handle
+            .join()
+            .inspect_err(|_| error!("The submitter thread for {} panicked", self.name))
+            .ok()
+    }
+}
+
+fn run(
+    receiver: Receiver<Message>,
+    mut submitters: Vec<Submitter>,
+    history: Arc<Mutex<History>>,
+) -> Vec<state::PendingScrobble> {
+    // The track shown as now playing, after running the filter script
+    let mut current_track = None;
+    // Plays that could not be added to the history yet, and the timestamps of the scrobbles
+    let mut unrecorded = Vec::new();
+    let mut record_retry_at = Instant::now();
+
+    loop {
+        match receiver.recv_timeout(RETRY_CHECK_INTERVAL) {
+            Ok(Message::Record(play)) => {
+                if !record(&history, &submitters, &play, None) {
+                    unrecorded.push((play, None));
+                }
+            }
+            Ok(Message::Scrobble { play, timestamp }) => {
+                if !record(&history, &submitters, &play, Some(timestamp)) {
+                    unrecorded.push((play, Some(timestamp)));
+                }
+            }
+            Ok(Message::NowPlaying(track)) => {
+                for submitter in submitters.iter() {
+                    submitter.send(Request::NowPlaying(track.clone()));
+                }
+
+                current_track = track;
+            }
+            Ok(Message::Reload(config)) => {
+                submitters = reload(submitters, &config, &history, current_track.as_ref());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // The main loop exited and all messages were handled
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if !unrecorded.is_empty() && Instant::now() >= record_retry_at {
+            debug!(
+                "Retrying to add {} play(s) to the history",
+                unrecorded.len()
+            );
+
+            unrecorded.retain(|(play, timestamp)| !record(&history, &submitters, play, *timestamp));
+            record_retry_at = Instant::now() + RECORD_RETRY_INTERVAL;
+        }
+    }
+
+    // Try one last time, and keep the remaining scrobbles in the state file
+    unrecorded.retain(|(play, timestamp)| !record(&history, &submitters, play, *timestamp));
+
+    let mut pending = Vec::new();
+
+    for (play, timestamp) in unrecorded {
+        let Some(timestamp) = timestamp else {
+            warn!(
+                "Could not add {} - {} to the history",
+                play.track.artist(),
+                play.track.title()
+            );
+            continue;
+        };
+
+        for submitter in submitters.iter() {
+            pending.push(state::PendingScrobble {
+                service: submitter.id.clone(),
+                service_name: submitter.name.clone(),
+                track: play.track.clone(),
+                timestamp: unix_timestamp(&timestamp),
+            });
+        }
+    }
+
+    for submitter in submitters {
+        submitter.stop();
+    }
+
+    pending
+}
+
+/// Stop the threads of the services, re-initialize the services whose settings
+/// changed, and start new threads for them. The backoff state of the services is kept,
+/// except for services that were paused after an authentication failure, which are
+/// re-initialized.
+fn reload(
+    submitters: Vec<Submitter>,
+    config: &Config,
+    history: &Arc<Mutex<History>>,
+    current_track: Option<&Track>,
+) -> Vec<Submitter> {
+    let mut backoffs = Backoffs::default();
+
+    let services = submitters
+        .into_iter()
+        .filter_map(Submitter::stop)
+        .filter_map(|(service, backoff)| backoffs.keep(service, backoff))
+        .collect();
+
+    Service::reinitialize(services, config)
+        .into_iter()
+        .filter_map(|service| {
+            let backoff = backoffs.take(&service);
+
+            Submitter::spawn(service, backoff, history.clone(), current_track.cloned())
+                .inspect_err(|err| error!("{:#}", err))
+                .ok()
+        })
+        .collect()
+}
+
+/// Handle the requests to a service until the worker stops, retrying failed
+/// requests in between. Returns the service and its backoff state.
+fn submit_requests(
+    receiver: Receiver<Request>,
+    service: Service,
+    mut backoff: Backoff,
+    history: &Mutex<History>,
+    mut current_track: Option<Track>,
+) -> (Service, Backoff) {
+    submit_pending(&service, history, &mut backoff);
+
+    loop {
+        match receiver.recv_timeout(RETRY_CHECK_INTERVAL) {
+            Ok(Request::Submit {
+                play,
+                track,
+                timestamp,
+            }) => {
+                submit(&service, history, &mut backoff, play, &track, &timestamp);
+            }
+            Ok(Request::NowPlaying(track)) => {
+                if let Some(ref track) = track {
+                    now_playing(&service, &mut backoff, track);
+                }
+
+                current_track = track;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        retry(&service, history, &mut backoff, current_track.as_ref());
+    }
+
+    (service, backoff)
+}
+
+/// Lock the history. It stays usable if another thread panicked while holding the lock,
+/// since every change to it is a single statement or transaction.
+fn lock(history: &Mutex<History>) -> MutexGuard<'_, History> {
+    history.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Add a play to the history, and if it is a scrobble (i.e. it has a timestamp),
+/// submit it to all services. Returns `false` if it could not be added.
+fn record(
+    history: &Mutex<History>,
+    submitters: &[Submitter],
+    play: &Play,
+    timestamp: Option<SystemTime>,
+) -> bool {
+    let id = match lock(history).add_play(play) {
+        Ok(id) => id,
+        Err(err) => {
+            error!("{:#}", err);
+            return false;
+        }
+    };
+
+    if let Some(timestamp) = timestamp {
+        for submitter in submitters {
+            submitter.send(Request::Submit {
+                play: id,
+                track: play.track.clone(),
+                timestamp,
+            });
+        }
+    }
+
+    true
+}
+
+/// Log a failed request to a service and update its backoff. Returns the kind of error.
+fn failed(service: &Service, backoff: &mut Backoff, err: &anyhow::Error) -> ErrorKind {
+    let (kind, retry_after) = retry::classify(err);
+
+    match backoff.failed(kind, retry_after, Instant::now()) {
+        Some(delay) => error!("{:#} (retrying in {}s)", err, delay.as_secs()),
+        None if kind == ErrorKind::Auth => error!(
+            "{:#}\nAuthentication with {} failed, scrobbles are kept until rescrobbled \
+            is restarted. To fix this, {}",
+            err,
+            service,
+            service.login_hint()
+        ),
+        None => warn!("{:#} (rejected by {}, not retrying)", err, service),
+    }
+
+    kind
+}
+
+/// Submit a "now playing" request, unless the service is unavailable.
+fn now_playing(service: &Service, backoff: &mut Backoff, track: &Track) {
+    if backoff.is_waiting(Instant::now()) {
+        debug!("Not updating status on {} while waiting to retry", service);
+        return;
+    }
+
+    match service.now_playing(track) {
+        Ok(()) => {
+            info!("Status updated on {} successfully", service);
+            backoff.succeeded();
+        }
+        Err(err) => {
+            failed(service, backoff, &err);
+        }
+    }
+}
+
+/// Log that a service corrected a scrobble, and record the correction in the history.
+fn record_correction(
+    service: &Service,
+    history: &Mutex<History>,
+    play: i64,
+    track: &Track,
+    corrected: &Track,
+) {
+    info!(
+        "{} corrected {} - {} to {} - {}",
+        service,
+        track.artist(),
+        track.title(),
+        corrected.artist(),
+        corrected.title()
+    );
+
+    if let Err(err) = lock(history).set_correction(play, &service.id(), corrected) {
+        error!("{:#}", err);
+    }
+}
+
+/// Submit a track to a service, and record the outcome in the history.
+/// Failed submissions are retried later; after a successful submission,
+/// the pending scrobbles for the service are retried.
+fn submit(
+    service: &Service,
+    history: &Mutex<History>,
+    backoff: &mut Backoff,
+    play: i64,
+    track: &Track,
+    timestamp: &SystemTime,
+) {
+    let mut corrected = None;
+
+    // While waiting to retry, the track is submitted later with the other pending scrobbles
+    let (status, error) = if backoff.is_waiting(Instant::now()) {
+        info!("{} is unavailable, track will be submitted later", service);
+        (Status::Pending, None)
+    } else {
+        match service.submit(track, timestamp) {
+            Ok(accepted) => {
+                info!("Track submitted to {} successfully", service);
+                backoff.succeeded();
+                corrected = accepted.corrected;
+                (Status::Submitted, None)
+            }
+            Err(err) => match failed(service, backoff, &err) {
+                ErrorKind::Rejected => (Status::Rejected, Some(err)),
+                _ => (Status::Pending, Some(err)),
+            },
+        }
+    };
+
+    if let Err(err) = lock(history).add_submission(
+        play,
+        &service.id(),
+        &service.to_string(),
+        unix_timestamp(timestamp),
+        status,
+        error.as_ref(),
+    ) {
+        error!("{:#}", err);
+    }
+
+    if let Some(ref corrected) = corrected {
+        record_correction(service, history, play, track, corrected);
+    }
+
+    if status == Status::Submitted {
+        submit_pending(service, history, backoff);
+    }
+}
+
+/// Submit pending scrobbles to a service, and record the outcome in the history.
+/// Returns `false` if the service failed and no more scrobbles should be submitted.
+fn submit_scrobbles(
+    service: &Service,
+    history: &Mutex<History>,
+    backoff: &mut Backoff,
+    scrobbles: &[PendingScrobble],
+) -> bool {
+    let id = service.id();
+
+    // Multiple scrobbles are submitted in a batch, to avoid a request per scrobble
+    let results = match scrobbles {
+        [scrobble] => service
+            .submit(&scrobble.track, &scrobble.timestamp())
+            .map(|accepted| vec![Ok(accepted)]),
+        _ => {
+            let batch: Vec<_> = scrobbles
+                .iter()
+                .map(|scrobble| (&scrobble.track, scrobble.timestamp))
+                .collect();
+
+            service.submit_batch(&batch)
+        }
+    };
+
+    let results = match results {
+        Ok(results) => results,
+        Err(err) => {
+            return match failed(service, backoff, &err) {
+                // Find out which scrobbles in the batch were rejected
+                ErrorKind::Rejected if scrobbles.len() > 1 => scrobbles
+                    .chunks(1)
+                    .all(|scrobble| submit_scrobbles(service, history, backoff, scrobble)),
+                ErrorKind::Rejected => {
+                    if let Err(err) = lock(history).mark_rejected(scrobbles[0].play, &id, &err) {
+                        error!("{:#}", err);
+                    }
+                    true
+                }
+                _ => {
+                    for scrobble in scrobbles {
+                        if let Err(err) = lock(history).mark_failed(scrobble.play, &id, &err) {
+                            error!("{:#}", err);
+                        }
+                    }
+                    false
+                }
+            };
+        }
+    };
+
+    backoff.succeeded();
+
+    let mut submitted = 0;
+    let mut keep_going = true;
+
+    for (scrobble, result) in scrobbles.iter().zip(results) {
+        match result {
+            Ok(accepted) => {
+                submitted += 1;
+
+                if let Err(err) = lock(history).mark_submitted(scrobble.play, &id) {
+                    error!("{:#}", err);
+                }
+
+                if let Some(ref corrected) = accepted.corrected {
+                    record_correction(service, history, scrobble.play, &scrobble.track, corrected);
+                }
+            }
+            // The service ignored this scrobble
+            Err(err) => match failed(service, backoff, &err) {
+                ErrorKind::Rejected => {
+                    if let Err(err) = lock(history).mark_rejected(scrobble.play, &id, &err) {
+                        error!("{:#}", err);
+                    }
+                }
+                _ => {
+                    if let Err(err) = lock(history).mark_failed(scrobble.play, &id, &err) {
+                        error!("{:#}", err);
+                    }
+                    keep_going = false;
+                }
+            },
+        }
+    }
+
+    match scrobbles {
+        [scrobble] if submitted == 1 => info!(
+            "Pending track {} - {} submitted to {} successfully",
+            scrobble.track.artist(),
+            scrobble.track.title(),
+            service,
+        ),
+        _ if submitted > 0 => info!(
+            "{} pending tracks submitted to {} successfully",
+            submitted, service
+        ),
+        _ => {}
+    }
+
+    keep_going
+}
+
+/// Retry the pending scrobbles for a service, oldest first.
+/// Stops when the service fails again.
+fn submit_pending(service: &Service, history: &Mutex<History>, backoff: &mut Backoff) {
+    if backoff.is_waiting(Instant::now()) {
+        return;
+    }
+
+    let pending = match lock(history).pending(&service.id()) {
+        Ok(pending) => pending,
+        Err(err) => {
+            error!("{:#}", err);
+            return;
+        }
+    };
+
+    if !pending.is_empty() {
+        debug!(
+            "Retrying {} pending scrobble(s) for {}",
+            pending.len(),
+            service
+        );
+    }
+
+    for batch in pending.chunks(service.batch_size()) {
+        if !submit_scrobbles(service, history, backoff, batch) {
+            break;
+        }
+    }
+}
+
+/// Retry the failed requests to a service, if its backoff delay has passed.
+fn retry(
+    service: &Service,
+    history: &Mutex<History>,
+    backoff: &mut Backoff,
+    current_track: Option<&Track>,
+) {
+    if backoff.take_due(Instant::now()) {
+        debug!("Retrying requests to {}", service);
+
+        if let Some(track) = current_track {
+            now_playing(service, backoff, track);
+        }
+
+        submit_pending(service, history, backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{Decision, Query};
+    use crate::service::{scrobble_response, serve};
+
+    fn play(title: &str, decision: Decision) -> Play {
+        Play {
+            track: Track::new("Nils Frahm", title, Some("All Melody")),
+            player: "org.mpris.MediaPlayer2.mpv".to_owned(),
+            started_at: 1_790_812_800,
+            play_time: Duration::from_secs(200),
+            length: Some(Duration::from_secs(300)),
+            decision,
+        }
+    }
+
+    #[test]
+    fn test_stop_waits_for_queue() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.sqlite");
+
+        let worker = Worker::spawn(Vec::new(), History::open_at(&path).unwrap()).unwrap();
+        worker.now_playing(Some(play("Sunson", Decision::Scrobbled).track));
+        worker.scrobble(play("Sunson", Decision::Scrobbled), None);
+        worker.record(play("Human Range", Decision::Skipped));
+        worker.stop();
+
+        let entries = History::open_at(&path)
+            .unwrap()
+            .entries(&Query::default())
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(
+            entries
+                .iter()
+                .any(|entry| entry.play.track.title() == "Human Range"
+                    && entry.play.decision == Decision::Skipped)
+        );
+    }
+
+    /// Make a history with a pending scrobble for each title.
+    fn pending(service: &Service, titles: &[&str]) -> Mutex<History> {
+        let history = History::open_in_memory().unwrap();
+
+        for (index, title) in titles.iter().enumerate() {
+            let play = Play {
+                started_at: 1_790_812_800 + index as u64 * 300,
+                ..play(title, Decision::Scrobbled)
+            };
+            let id = history.add_play(&play).unwrap();
+
+            history
+                .add_submission(
+                    id,
+                    &service.id(),
+                    &service.to_string(),
+                    play.started_at,
+                    Status::Pending,
+                    None,
+                )
+                .unwrap();
+        }
+
+        Mutex::new(history)
+    }
+
+    fn statuses(history: &Mutex<History>) -> Vec<(Status, u32)> {
+        lock(history)
+            .entries(&Query::default())
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.submissions[0].status, entry.submissions[0].attempts))
+            .collect()
+    }
+
+    #[test]
+    fn test_submit_pending_batch() {
+        let (url, server) = serve(&[(200, &scrobble_response(&[None, None, None]))]);
+        let service = Service::mock_lastfm(&url);
+        let history = pending(&service, &["Sunson", "Human Range", "Forever Changeless"]);
+
+        submit_pending(&service, &history, &mut Backoff::default());
+
+        // All scrobbles are submitted in one request
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("track%5B2%5D=Forever+Changeless"));
+
+        assert_eq!(statuses(&history), vec![(Status::Submitted, 1); 3]);
+    }
+
+    #[test]
+    fn test_submit_pending_rejected() {
+        let invalid = r#"{"error": 6, "message": "Invalid parameters"}"#;
+        let (url, server) = serve(&[
+            (400, invalid),
+            (200, &scrobble_response(&[None])),
+            (400, invalid),
+            (200, &scrobble_response(&[None])),
+        ]);
+        let service = Service::mock_lastfm(&url);
+        let history = pending(&service, &["Sunson", "Human Range", "Forever Changeless"]);
+
+        let mut backoff = Backoff::default();
+        submit_pending(&service, &history, &mut backoff);
+
+        // The batch is split to find the scrobble that was rejected
+        assert_eq!(server.join().unwrap().len(), 4);
+        assert_eq!(
+            statuses(&history),
+            vec![
+                (Status::Submitted, 1),
+                (Status::Rejected, 1),
+                (Status::Submitted, 1),
+            ]
+        );
+        assert!(!backoff.is_waiting(Instant::now()));
+    }
+
+    #[test]
+    fn test_submit_pending_backoff() {
+        let (url, server) = serve(&[(500, "")]);
+        let service = Service::mock_lastfm(&url);
+        let history = pending(&service, &["Sunson", "Human Range"]);
+
+        let mut backoff = Backoff::default();
+        submit_pending(&service, &history, &mut backoff);
+        assert!(backoff.is_waiting(Instant::now()));
+
+        // While waiting to retry, no requests are made
+        submit_pending(&service, &history, &mut backoff);
+
+        let play = Play {
+            started_at: 1_790_813_400,
+            ..play("All Melody", Decision::Scrobbled)
+        };
+        let id = lock(&history).add_play(&play).unwrap();
+        submit(
+            &service,
+            &history,
+            &mut backoff,
+            id,
+            &play.track,
+            &SystemTime::now(),
+        );
+
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert_eq!(
+            statuses(&history),
+            vec![
+                (Status::Pending, 1),
+                (Status::Pending, 1),
+                (Status::Pending, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stop_keeps_unrecorded() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.sqlite");
+
+        let (url, server) = serve(&[]);
+        let worker = Worker::spawn(
+            vec![Service::mock_lastfm(&url)],
+            History::open_at(&path).unwrap(),
+        )
+        .unwrap();
+
+        // Make adding plays fail
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch("ALTER TABLE plays RENAME TO moved")
+            .unwrap();
+
+        let play = play("Sunson", Decision::Scrobbled);
+        worker.scrobble(play.clone(), Some(SystemTime::UNIX_EPOCH));
+        worker.record(play.clone());
+
+        assert_eq!(
+            worker.stop(),
+            vec![state::PendingScrobble {
+                service: "lastfm:key".to_owned(),
+                service_name: "Last.fm".to_owned(),
+                track: play.track,
+                timestamp: 0,
+            }]
+        );
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pending_from_state_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("history.sqlite");
+
+        let mut history = History::open_at(&path).unwrap();
+        history
+            .import_pending(&[state::PendingScrobble {
+                service: "lastfm:key".to_owned(),
+                service_name: "Last.fm".to_owned(),
+                track: play("Sunson", Decision::Scrobbled).track,
+                timestamp: 1_790_812_800,
+            }])
+            .unwrap();
+
+        let (url, server) = serve(&[(200, &scrobble_response(&[None]))]);
+        let worker = Worker::spawn(vec![Service::mock_lastfm(&url)], history).unwrap();
+
+        assert_eq!(server.join().unwrap().len(), 1);
+        worker.stop();
+
+        let entries = History::open_at(&path)
+            .unwrap()
+            .entries(&Query::default())
+            .unwrap();
+        assert_eq!(entries[0].submissions[0].status, Status::Submitted);
+        assert_eq!(entries[0].submissions[0].service, "Last.fm");
+    }
+}